@@ -0,0 +1,281 @@
+use crate::report::{self, BatchFailure, Kind};
+use anyhow::Result;
+use matknife::image_file;
+use rayon::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Lists the layers of a multi-layer EXR file as separate paths, so that
+/// each layer is picked up like a file of its own
+fn layers(file: PathBuf) -> Result<Vec<PathBuf>> {
+    let layers = image_file::layers(&file)?;
+
+    if layers.is_empty() {
+        return Ok(vec![file]);
+    }
+
+    Ok(layers
+        .iter()
+        .map(|layer| image_file::with_layer(&file, layer))
+        .collect())
+}
+
+/// Whether part of a path is a glob pattern rather than a plain name
+fn is_pattern(part: &str) -> bool {
+    part.contains(['*', '?', '['])
+}
+
+/// Matches one part of a path against one part of a glob pattern, where
+/// `*` matches any run of characters, `?` matches any one character and
+/// `[abc]` matches any one of the characters listed
+fn matches_part(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches_part(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && matches_part(rest, &name[1..]),
+        Some(('[', rest)) => match rest.iter().position(|character| *character == ']') {
+            Some(end) => {
+                !name.is_empty()
+                    && rest[..end].contains(&name[0])
+                    && matches_part(&rest[end + 1..], &name[1..])
+            }
+            None => name.first() == Some(&'[') && matches_part(rest, &name[1..]),
+        },
+        Some((character, rest)) => {
+            name.first() == Some(character) && matches_part(rest, &name[1..])
+        }
+    }
+}
+
+/// Matches the parts of a path against the parts of a glob pattern, where
+/// a `**` part matches any number of directories
+fn matches_parts(pattern: &[Vec<char>], path: &[Vec<char>]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((part, rest)) if part.iter().collect::<String>() == "**" => {
+            (0..=path.len()).any(|skip| matches_parts(rest, &path[skip..]))
+        }
+        Some((part, rest)) => {
+            !path.is_empty() && matches_part(part, &path[0]) && matches_parts(rest, &path[1..])
+        }
+    }
+}
+
+fn parts(path: &Path) -> Vec<Vec<char>> {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy().chars().collect())
+        .collect()
+}
+
+/// Lists the files in a directory, and in its subdirectories down to
+/// `depth` levels in all, in a stable order
+///
+/// A depth of 1 lists just the files in the directory itself. Links to
+/// directories are not followed, as they can lead back up to a directory
+/// being walked.
+fn walk(directory: &Path, depth: usize, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut paths = fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            if fs::symlink_metadata(&path)?.file_type().is_symlink() {
+                debug!("Skipping {:?}, which links to a directory", path);
+            } else if depth > 1 {
+                walk(&path, depth - 1, files)?;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Expands a list of files, directories and glob patterns into a list of
+/// files
+///
+/// Files are kept as they are. Directories, and the matches of glob
+/// patterns, are filtered down to the files `include` accepts, so that only
+/// the textures a command works on are picked up. Subdirectories are only
+/// looked in if `recursive` is set, or as deep as a pattern needs, which is
+/// any depth if it has a `**` in it. Files given more than once are only
+/// listed once.
+///
+/// Multi-layer EXR files are expanded into their layers, given as
+/// `<file>#<layer>`, which are filtered down the same way unless a single
+/// layer was given.
+pub fn expand(
+    paths: &[PathBuf],
+    recursive: bool,
+    include: impl Fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if path.is_file() {
+            let layers = layers(path.clone())?;
+
+            if layers.len() == 1 {
+                files.extend(layers);
+            } else {
+                files.extend(layers.into_iter().filter(|file| include(file)));
+            }
+        } else if image_file::is_image(path) {
+            files.push(path.clone());
+        } else if path.is_dir() {
+            let mut found = Vec::new();
+            walk(path, if recursive { usize::MAX } else { 1 }, &mut found)?;
+
+            for file in found {
+                files.extend(layers(file)?.into_iter().filter(|file| include(file)));
+            }
+        } else if is_pattern(&path.to_string_lossy()) {
+            // Only the directories leading up to the first pattern need
+            // looking in, rather than the whole file system
+            let base: PathBuf = path
+                .components()
+                .take_while(|component| {
+                    matches!(component, Component::Prefix(_) | Component::RootDir)
+                        || !is_pattern(&component.as_os_str().to_string_lossy())
+                })
+                .collect();
+
+            let pattern = parts(path);
+
+            // Patterns without `**` only match files as deep as they go
+            let depth = if pattern
+                .iter()
+                .any(|part| part.iter().collect::<String>() == "**")
+            {
+                usize::MAX
+            } else {
+                pattern.len() - parts(&base).len()
+            };
+
+            let mut found = Vec::new();
+
+            walk(
+                if base.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    &base
+                },
+                depth,
+                &mut found,
+            )?;
+
+            let mut matched = Vec::new();
+
+            for file in found {
+                let file = match file.strip_prefix(".") {
+                    Ok(stripped) if base.as_os_str().is_empty() => stripped.to_path_buf(),
+                    _ => file,
+                };
+
+                if matches_parts(&pattern, &parts(&file)) {
+                    matched.extend(layers(file)?.into_iter().filter(|file| include(file)));
+                }
+            }
+
+            if matched.is_empty() {
+                warn!("No files matched {:?}", path);
+            }
+
+            files.extend(matched);
+        } else {
+            let message = format!("{:?} does not exist!", path);
+
+            return Err(io::Error::new(io::ErrorKind::NotFound, message).into());
+        }
+    }
+
+    // The same file can be given more than once, directly, through its
+    // directory or by several patterns, and written in different ways, such
+    // as `./Rusty.png` and `Rusty.png`
+    let mut seen = BTreeSet::new();
+
+    files.retain(|file| {
+        let (path, layer) = image_file::split_layer(file);
+
+        seen.insert((path.canonicalize().unwrap_or(path), layer))
+    });
+
+    Ok(files)
+}
+
+/// Runs a job for each item on every CPU core, returning the name of each
+/// item and how its job went, in the same order as the items
+///
+/// What each job does is recorded for the report under the item's name.
+pub fn run<T: Sync>(
+    items: &[T],
+    name: impl Fn(&T) -> String + Send + Sync,
+    job: impl Fn(&T) -> Result<()> + Send + Sync,
+) -> Vec<(String, Result<()>)> {
+    items
+        .par_iter()
+        .enumerate()
+        .map(|(position, item)| {
+            let name = name(item);
+            let outcome = report::record(position, name.clone(), || job(item));
+
+            (name, outcome)
+        })
+        .collect()
+}
+
+/// Prints how each job went, failing if any of them failed
+///
+/// The batch fails with an I/O error if every job failed that way, a
+/// validation error if every job failed and any of them for another
+/// reason, and a partial failure if only some jobs failed.
+pub fn summarise(outcomes: Vec<(String, Result<()>)>) -> Result<()> {
+    let failures = outcomes
+        .iter()
+        .filter(|(_, outcome)| outcome.is_err())
+        .count();
+
+    if !report::is_json() && (outcomes.len() > 1 || failures > 0) {
+        println!(
+            "Processed {} files: {} succeeded, {} failed",
+            outcomes.len(),
+            outcomes.len() - failures,
+            failures
+        );
+
+        for (name, outcome) in &outcomes {
+            match outcome {
+                Ok(()) => println!("  ok      {}", name),
+                Err(error) => println!("  failed  {}: {:#}", name, error),
+            }
+        }
+    }
+
+    if failures > 0 {
+        let kind = if failures < outcomes.len() {
+            Kind::Partial
+        } else if outcomes
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, Err(error) if report::kind(error) == Kind::Io))
+        {
+            Kind::Io
+        } else {
+            Kind::Validation
+        };
+
+        return Err(BatchFailure {
+            failed: failures,
+            total: outcomes.len(),
+            kind,
+        }
+        .into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,438 @@
+use image::RgbaImage;
+
+/// The pixels of a 4x4 block, row by row
+pub type Block = [[u8; 4]; 16];
+
+/// The BC7 mode 6 interpolation weights, out of 64
+const BC7_WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Splits an image into 4x4 blocks, row by row, repeating the last row and
+/// column of pixels to fill blocks which go past the edge
+pub fn blocks(image: &RgbaImage) -> Vec<Block> {
+    let (width, height) = image.dimensions();
+    let mut blocks = Vec::new();
+
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            let mut block = [[0; 4]; 16];
+
+            for (index, pixel) in block.iter_mut().enumerate() {
+                let x_position = (block_x + index as u32 % 4).min(width - 1);
+                let y_position = (block_y + index as u32 / 4).min(height - 1);
+
+                *pixel = image.get_pixel(x_position, y_position).0;
+            }
+
+            blocks.push(block);
+        }
+    }
+
+    blocks
+}
+
+/// Finds the two ends of the line which best fits some points, by projecting
+/// them onto the direction they vary most in
+fn principal_endpoints<const N: usize>(points: &[[f32; N]]) -> ([f32; N], [f32; N]) {
+    let count = points.len() as f32;
+    let mut mean = [0.0; N];
+
+    for point in points {
+        for (total, value) in mean.iter_mut().zip(point) {
+            *total += value / count;
+        }
+    }
+
+    let mut covariance = [[0.0; N]; N];
+
+    for point in points {
+        for row in 0..N {
+            for column in 0..N {
+                covariance[row][column] +=
+                    (point[row] - mean[row]) * (point[column] - mean[column]);
+            }
+        }
+    }
+
+    // Power iteration, starting from the longest side of the bounding box
+    let mut axis = [0.0; N];
+
+    for (channel, value) in axis.iter_mut().enumerate() {
+        let (low, high) = points
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(low, high), point| {
+                (low.min(point[channel]), high.max(point[channel]))
+            });
+
+        *value = high - low;
+    }
+
+    for _ in 0..8 {
+        let mut next = [0.0; N];
+
+        for (row, value) in next.iter_mut().enumerate() {
+            *value = (0..N)
+                .map(|column| covariance[row][column] * axis[column])
+                .sum();
+        }
+
+        let length = next.iter().map(|value| value * value).sum::<f32>().sqrt();
+
+        if length < f32::EPSILON {
+            break;
+        }
+
+        axis = next.map(|value| value / length);
+    }
+
+    let (low, high) = points
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(low, high), point| {
+            let projection: f32 = (0..N)
+                .map(|channel| (point[channel] - mean[channel]) * axis[channel])
+                .sum();
+
+            (low.min(projection), high.max(projection))
+        });
+
+    let mut start = [0.0; N];
+    let mut end = [0.0; N];
+
+    for channel in 0..N {
+        start[channel] = (mean[channel] + axis[channel] * low).clamp(0.0, 255.0);
+        end[channel] = (mean[channel] + axis[channel] * high).clamp(0.0, 255.0);
+    }
+
+    (start, end)
+}
+
+fn distance<const N: usize>(first: &[f32; N], second: &[f32; N]) -> f32 {
+    first
+        .iter()
+        .zip(second)
+        .map(|(first, second)| (first - second) * (first - second))
+        .sum()
+}
+
+/// The index of the palette entry closest to a point
+fn nearest<const N: usize>(palette: &[[f32; N]], point: &[f32; N]) -> usize {
+    (0..palette.len())
+        .min_by(|first, second| {
+            distance(&palette[*first], point).total_cmp(&distance(&palette[*second], point))
+        })
+        .unwrap_or(0)
+}
+
+fn to_565(colour: [f32; 3]) -> u16 {
+    let red = (colour[0] * 31.0 / 255.0).round() as u16;
+    let green = (colour[1] * 63.0 / 255.0).round() as u16;
+    let blue = (colour[2] * 31.0 / 255.0).round() as u16;
+
+    (red << 11) | (green << 5) | blue
+}
+
+fn from_565(colour: u16) -> [f32; 3] {
+    let red = (colour >> 11) & 0x1f;
+    let green = (colour >> 5) & 0x3f;
+    let blue = colour & 0x1f;
+
+    [
+        ((red << 3) | (red >> 2)) as f32,
+        ((green << 2) | (green >> 4)) as f32,
+        ((blue << 3) | (blue >> 2)) as f32,
+    ]
+}
+
+/// Finds the endpoints which best fit some points, given how far along
+/// the line between the endpoints each point is meant to be
+///
+/// Returns nothing if every point is meant to be at the same place.
+fn fit_endpoints<const N: usize>(
+    points: &[[f32; N]],
+    weights: &[f32],
+) -> Option<([f32; N], [f32; N])> {
+    let (mut start_start, mut start_end, mut end_end) = (0.0, 0.0, 0.0);
+    let (mut start_sum, mut end_sum) = ([0.0; N], [0.0; N]);
+
+    for (point, weight) in points.iter().zip(weights) {
+        start_start += (1.0 - weight) * (1.0 - weight);
+        start_end += weight * (1.0 - weight);
+        end_end += weight * weight;
+
+        for channel in 0..N {
+            start_sum[channel] += (1.0 - weight) * point[channel];
+            end_sum[channel] += weight * point[channel];
+        }
+    }
+
+    let determinant = start_start * end_end - start_end * start_end;
+
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let mut start = [0.0; N];
+    let mut end = [0.0; N];
+
+    for channel in 0..N {
+        start[channel] = ((end_end * start_sum[channel] - start_end * end_sum[channel])
+            / determinant)
+            .clamp(0.0, 255.0);
+        end[channel] = ((start_start * end_sum[channel] - start_end * start_sum[channel])
+            / determinant)
+            .clamp(0.0, 255.0);
+    }
+
+    Some((start, end))
+}
+
+/// Encodes a BC1 colour block between two endpoints, returning it with its
+/// squared error and how far along the line each pixel ended up
+fn bc1_between(points: &[[f32; 3]], start: [f32; 3], end: [f32; 3]) -> ([u8; 8], f32, Vec<f32>) {
+    let (mut first, mut second) = (to_565(end), to_565(start));
+
+    // The first colour must be the larger one for four-colour blocks
+    if first < second {
+        std::mem::swap(&mut first, &mut second);
+    }
+
+    let (first_colour, second_colour) = (from_565(first), from_565(second));
+    let mut palette = [first_colour, second_colour, [0.0; 3], [0.0; 3]];
+
+    for channel in 0..3 {
+        palette[2][channel] = (2.0 * first_colour[channel] + second_colour[channel]) / 3.0;
+        palette[3][channel] = (first_colour[channel] + 2.0 * second_colour[channel]) / 3.0;
+    }
+
+    let mut indices = 0_u32;
+    let mut error = 0.0;
+    let mut weights = Vec::with_capacity(points.len());
+
+    for (index, point) in points.iter().enumerate() {
+        // Equal colours make a three-colour block, where only the first
+        // entry is the same colour
+        let entry = if first == second {
+            0
+        } else {
+            nearest(&palette, point)
+        };
+
+        indices |= (entry as u32) << (2 * index);
+        error += distance(&palette[entry], point);
+        weights.push([0.0, 1.0, 1.0 / 3.0, 2.0 / 3.0][entry]);
+    }
+
+    let mut compressed = [0; 8];
+    compressed[0..2].copy_from_slice(&first.to_le_bytes());
+    compressed[2..4].copy_from_slice(&second.to_le_bytes());
+    compressed[4..8].copy_from_slice(&indices.to_le_bytes());
+
+    (compressed, error, weights)
+}
+
+/// Compresses the colour of a block as a BC1 block, ignoring alpha
+pub fn bc1(block: &Block) -> [u8; 8] {
+    let points: Vec<[f32; 3]> = block
+        .iter()
+        .map(|pixel| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32])
+        .collect();
+
+    let (start, end) = principal_endpoints(&points);
+    let (compressed, error, weights) = bc1_between(&points, start, end);
+
+    // Fitting again to where the pixels ended up in the palette, which runs
+    // from the first colour to the second, often loses less
+    match fit_endpoints(&points, &weights) {
+        Some((first, second)) => {
+            let (refined, refined_error, _) = bc1_between(&points, second, first);
+
+            if refined_error < error {
+                refined
+            } else {
+                compressed
+            }
+        }
+        None => compressed,
+    }
+}
+
+/// Compresses one channel of a block as a BC4 block
+pub fn bc4(block: &Block, channel: usize) -> [u8; 8] {
+    let values: Vec<u8> = block.iter().map(|pixel| pixel[channel]).collect();
+
+    let first = values.iter().copied().max().unwrap_or(0);
+    let second = values.iter().copied().min().unwrap_or(0);
+
+    let mut indices = 0_u64;
+
+    if first != second {
+        // Eight-value blocks, with six values between the two ends
+        let mut palette = [
+            [first as f32],
+            [second as f32],
+            [0.0],
+            [0.0],
+            [0.0],
+            [0.0],
+            [0.0],
+            [0.0],
+        ];
+
+        for step in 1..7 {
+            palette[step + 1] =
+                [((7 - step) as f32 * first as f32 + step as f32 * second as f32) / 7.0];
+        }
+
+        for (index, value) in values.iter().enumerate() {
+            indices |= (nearest(&palette, &[*value as f32]) as u64) << (3 * index);
+        }
+    }
+
+    let mut compressed = [0; 8];
+    compressed[0] = first;
+    compressed[1] = second;
+    compressed[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+
+    compressed
+}
+
+/// Compresses a block as a BC3 block, with colour as in BC1 and alpha as in
+/// BC4
+pub fn bc3(block: &Block) -> [u8; 16] {
+    let mut compressed = [0; 16];
+    compressed[..8].copy_from_slice(&bc4(block, 3));
+    compressed[8..].copy_from_slice(&bc1(block));
+
+    compressed
+}
+
+/// Compresses the red and green channels of a block as a BC5 block, made of
+/// two BC4 blocks
+pub fn bc5(block: &Block) -> [u8; 16] {
+    let mut compressed = [0; 16];
+    compressed[..8].copy_from_slice(&bc4(block, 0));
+    compressed[8..].copy_from_slice(&bc4(block, 1));
+
+    compressed
+}
+
+/// Writes values into a block of bits, lowest bits first
+struct Bits {
+    bits: u128,
+    position: u32,
+}
+
+impl Bits {
+    fn push(&mut self, value: u32, count: u32) {
+        self.bits |= ((value & ((1 << count) - 1)) as u128) << self.position;
+        self.position += count;
+    }
+}
+
+/// Quantises an endpoint to the 7 bits per channel and shared low bit of
+/// BC7 mode 6, choosing the low bit which loses least
+fn bc7_endpoint(endpoint: [f32; 4]) -> ([u32; 4], u32) {
+    (0..2)
+        .map(|low_bit| {
+            let channels = endpoint
+                .map(|value| ((value - low_bit as f32) / 2.0).round().clamp(0.0, 127.0) as u32);
+
+            let error: f32 = (0..4)
+                .map(|channel| {
+                    let value = ((channels[channel] << 1) | low_bit) as f32;
+
+                    (value - endpoint[channel]) * (value - endpoint[channel])
+                })
+                .sum();
+
+            (channels, low_bit, error)
+        })
+        .min_by(|first, second| first.2.total_cmp(&second.2))
+        .map(|(channels, low_bit, _)| (channels, low_bit))
+        .unwrap_or(([0; 4], 0))
+}
+
+/// Encodes a BC7 mode 6 block between two endpoints, returning it with its
+/// squared error and how far along the line each pixel ended up
+fn bc7_between(points: &[[f32; 4]], start: [f32; 4], end: [f32; 4]) -> ([u8; 16], f32, Vec<f32>) {
+    let (mut first, mut first_bit) = bc7_endpoint(start);
+    let (mut second, mut second_bit) = bc7_endpoint(end);
+
+    let palette = BC7_WEIGHTS.map(|weight| {
+        let mut colour = [0.0; 4];
+
+        for channel in 0..4 {
+            let low = (first[channel] << 1) | first_bit;
+            let high = (second[channel] << 1) | second_bit;
+
+            colour[channel] = (((64 - weight) * low + weight * high + 32) >> 6) as f32;
+        }
+
+        colour
+    });
+
+    let mut indices: Vec<u32> = Vec::with_capacity(points.len());
+    let mut error = 0.0;
+    let mut weights = Vec::with_capacity(points.len());
+
+    for point in points {
+        let entry = nearest(&palette, point);
+
+        indices.push(entry as u32);
+        error += distance(&palette[entry], point);
+        weights.push(BC7_WEIGHTS[entry] as f32 / 64.0);
+    }
+
+    // The first pixel's index is stored without its top bit, so it must be
+    // in the lower half, which swapping the endpoints makes it
+    if indices[0] >= 8 {
+        std::mem::swap(&mut first, &mut second);
+        std::mem::swap(&mut first_bit, &mut second_bit);
+
+        for index in &mut indices {
+            *index = 15 - *index;
+        }
+    }
+
+    let mut bits = Bits {
+        bits: 0,
+        position: 0,
+    };
+
+    bits.push(1 << 6, 7);
+
+    for channel in 0..4 {
+        bits.push(first[channel], 7);
+        bits.push(second[channel], 7);
+    }
+
+    bits.push(first_bit, 1);
+    bits.push(second_bit, 1);
+
+    for (position, index) in indices.iter().enumerate() {
+        bits.push(*index, if position == 0 { 3 } else { 4 });
+    }
+
+    (bits.bits.to_le_bytes(), error, weights)
+}
+
+/// Compresses a block as a BC7 block, using mode 6, which holds colour and
+/// alpha together with 16 steps between two endpoints
+pub fn bc7(block: &Block) -> [u8; 16] {
+    let points: Vec<[f32; 4]> = block.iter().map(|pixel| pixel.map(f32::from)).collect();
+
+    let (start, end) = principal_endpoints(&points);
+    let (compressed, error, weights) = bc7_between(&points, start, end);
+
+    match fit_endpoints(&points, &weights) {
+        Some((start, end)) => {
+            let (refined, refined_error, _) = bc7_between(&points, start, end);
+
+            if refined_error < error {
+                refined
+            } else {
+                compressed
+            }
+        }
+        None => compressed,
+    }
+}
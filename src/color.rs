@@ -0,0 +1,278 @@
+use crate::curve;
+use crate::image_file;
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How the values stored in a texture relate to the light they stand for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Values are encoded with the sRGB transfer function, as colour
+    /// textures such as base colour usually are
+    Srgb,
+    /// Values are stored as-is, as data textures such as metallic and
+    /// roughness must be
+    Linear,
+}
+
+/// The command line names of every colour space
+pub const NAMES: &[&str] = &["srgb", "linear"];
+
+impl FromStr for ColorSpace {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "srgb" => Ok(ColorSpace::Srgb),
+            "linear" => Ok(ColorSpace::Linear),
+            _ => bail!(
+                "Unknown colour space {:?}, expected one of {}",
+                name,
+                NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            ColorSpace::Srgb => "sRGB",
+            ColorSpace::Linear => "linear",
+        })
+    }
+}
+
+/// Decodes a value from 0 to 1 with the sRGB transfer function
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a value from 0 to 1 with the sRGB transfer function
+pub fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts the colour channels of an image from one colour space to
+/// another, leaving alpha as-is
+pub fn convert(image: DynamicImage, from: ColorSpace, to: ColorSpace) -> DynamicImage {
+    match (from, to) {
+        (ColorSpace::Srgb, ColorSpace::Linear) => curve::remap(&image, srgb_to_linear),
+        (ColorSpace::Linear, ColorSpace::Srgb) => curve::remap(&image, linear_to_srgb),
+        _ => image,
+    }
+}
+
+/// Opens an image file holding something usually stored in `color_space`,
+/// converting it to that colour space if it is stored in another one
+///
+/// The file is taken to be stored in `stored` if it is given. Otherwise
+/// EXR and HDR files, which can only hold linear values, are converted from
+/// linear, and other files are taken to be in `color_space` already. PNG
+/// files whose sRGB, gAMA or iCCP chunks say otherwise are not converted,
+/// as tools often tag data such as roughness as sRGB, but a warning says
+/// how to convert them.
+pub fn open(
+    path: &Path,
+    color_space: ColorSpace,
+    stored: Option<ColorSpace>,
+) -> Result<DynamicImage> {
+    let image = image_file::open(path)?;
+
+    let stored = match stored {
+        Some(stored) => stored,
+        None if image_file::is_linear_format(path) => ColorSpace::Linear,
+        None => {
+            if let Some(tagged) = image_file::color_space(path)? {
+                if tagged != color_space {
+                    warn!(
+                        "{:?} is tagged as {}, but is read as {} as what it holds usually is. Use --input-colorspace {} to convert it",
+                        path,
+                        tagged,
+                        color_space,
+                        tagged.to_string().to_ascii_lowercase()
+                    );
+                }
+            }
+
+            color_space
+        }
+    };
+
+    if stored != color_space {
+        debug!("Converting {:?} from {} to {}", path, stored, color_space);
+    }
+
+    Ok(convert(image, stored, color_space))
+}
+
+/// The sRGB primaries, adapted to the D50 white point ICC profiles use
+const SRGB_COLORANTS: [[f64; 3]; 3] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.3851, 0.7169, 0.0971],
+    [0.1431, 0.0606, 0.7141],
+];
+
+/// The D50 white point ICC profiles use
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+
+    for value in xyz {
+        tag.extend(s15_fixed16(value));
+    }
+
+    tag
+}
+
+/// Builds an ICC profile with a linear transfer function and the sRGB
+/// primaries, for tagging linear PNG files
+///
+/// Greyscale images need a greyscale profile, so `color` says which to
+/// build.
+pub fn linear_icc_profile(color: bool) -> Vec<u8> {
+    let description = if color { "Linear sRGB" } else { "Linear Grey" };
+
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    desc.extend((description.len() as u32 + 1).to_be_bytes());
+    desc.extend(description.as_bytes());
+    desc.extend([0; 1 + 4 + 4 + 2 + 1 + 67]);
+
+    let mut cprt = b"text\0\0\0\0No copyright, use freely".to_vec();
+    cprt.push(0);
+
+    // A curve with a single entry is a gamma, of 1.0 here
+    let trc = b"curv\0\0\0\0\0\0\0\x01\x01\0\0\0".to_vec();
+
+    let mut tags: Vec<(&[u8; 4], Vec<u8>)> =
+        vec![(b"desc", desc), (b"cprt", cprt), (b"wtpt", xyz_tag(D50))];
+
+    if color {
+        tags.push((b"rXYZ", xyz_tag(SRGB_COLORANTS[0])));
+        tags.push((b"gXYZ", xyz_tag(SRGB_COLORANTS[1])));
+        tags.push((b"bXYZ", xyz_tag(SRGB_COLORANTS[2])));
+        tags.push((b"rTRC", trc.clone()));
+        tags.push((b"gTRC", trc.clone()));
+        tags.push((b"bTRC", trc));
+    } else {
+        tags.push((b"kTRC", trc));
+    }
+
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let data_start = 128 + 4 + 12 * tags.len();
+
+    for (signature, tag) in &tags {
+        table.extend(*signature);
+        table.extend(((data_start + data.len()) as u32).to_be_bytes());
+        table.extend((tag.len() as u32).to_be_bytes());
+
+        data.extend(tag);
+        data.resize(data.len().div_ceil(4) * 4, 0);
+    }
+
+    let size = 128 + table.len() + data.len();
+
+    let mut profile = Vec::with_capacity(size);
+    profile.extend((size as u32).to_be_bytes());
+    profile.extend([0; 4]);
+    profile.extend(0x0210_0000_u32.to_be_bytes());
+    profile.extend(b"mntr");
+    profile.extend(if color { b"RGB " } else { b"GRAY" });
+    profile.extend(b"XYZ ");
+    profile.extend([0; 12]);
+    profile.extend(b"acsp");
+    profile.extend([0; 24]);
+    profile.extend(0_u32.to_be_bytes());
+
+    for value in D50 {
+        profile.extend(s15_fixed16(value));
+    }
+
+    profile.resize(128, 0);
+    profile.extend(table);
+    profile.extend(data);
+
+    profile
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+/// Works out the colour space of an ICC profile from its transfer function
+///
+/// Profiles whose transfer function is a straight line are linear, and any
+/// other transfer function is taken to be sRGB. Returns nothing if the
+/// profile cannot be understood.
+pub fn icc_color_space(profile: &[u8]) -> Option<ColorSpace> {
+    let count = read_u32(profile, 128)? as usize;
+
+    let (offset, size) = (0..count).find_map(|index| {
+        let entry = 128 + 4 + 12 * index;
+        let signature = profile.get(entry..entry + 4)?;
+
+        if signature == b"rTRC" || signature == b"kTRC" {
+            Some((
+                read_u32(profile, entry + 4)? as usize,
+                read_u32(profile, entry + 8)? as usize,
+            ))
+        } else {
+            None
+        }
+    })?;
+
+    let trc = profile.get(offset..offset + size)?;
+
+    let linear = match trc.get(0..4)? {
+        b"curv" => match read_u32(trc, 8)? {
+            0 => true,
+            1 => read_u16(trc, 12)? == 0x0100,
+            entries => {
+                // A table which goes straight from black to white is linear
+                let middle = read_u16(trc, 12 + 2 * (entries as usize / 2))? as f32 / 65535.0;
+                let expected = (entries / 2) as f32 / (entries - 1) as f32;
+
+                (middle - expected).abs() < 0.01
+            }
+        },
+        b"para" => {
+            let function = read_u16(trc, 8)?;
+            let gamma = read_u32(trc, 12)? as i32 as f32 / 65536.0;
+
+            function == 0 && (gamma - 1.0).abs() < 0.01
+        }
+        _ => return None,
+    };
+
+    Some(if linear {
+        ColorSpace::Linear
+    } else {
+        ColorSpace::Srgb
+    })
+}
@@ -0,0 +1,68 @@
+use crate::output::OutputOptions;
+use crate::report;
+use anyhow::Result;
+use image::DynamicImage;
+use matknife::color::{self, ColorSpace};
+use matknife::mipmap::Toksvig;
+use matknife::texture_set::Texture;
+use std::path::Path;
+use structopt::StructOpt;
+
+/// Options for which colour spaces input and output files are in
+#[derive(Debug, StructOpt)]
+pub struct ColorSpaceOptions {
+    /// The colour space input files are stored in, either `srgb` or
+    /// `linear`
+    ///
+    /// Without one, EXR and HDR files are linear, and other files are
+    /// taken to be in the usual colour space of what they hold: sRGB for
+    /// base colour, diffuse, specular and emission, and linear for
+    /// everything else. PNG files tagged with another colour space by their
+    /// sRGB, gAMA or iCCP chunks are read the usual way too, with a warning
+    #[structopt(long, possible_values = color::NAMES)]
+    pub input_colorspace: Option<ColorSpace>,
+
+    /// The colour space to write output files in, either `srgb` or `linear`
+    ///
+    /// Without one, each output file is written in the usual colour space of
+    /// what it holds. PNG output files are tagged with their colour space
+    #[structopt(long, possible_values = color::NAMES)]
+    pub output_colorspace: Option<ColorSpace>,
+}
+
+impl ColorSpaceOptions {
+    /// Opens an input file holding something usually stored in
+    /// `color_space`, converting it to that colour space if it is stored in
+    /// another one
+    pub fn open(&self, path: &Path, color_space: ColorSpace) -> Result<DynamicImage> {
+        report::input(path);
+
+        color::open(path, color_space, self.input_colorspace)
+    }
+
+    /// Writes an output file holding a texture, converting it from the
+    /// texture's usual colour space to the output colour space if one was
+    /// given, and tagging it with the colour space it is written in
+    ///
+    /// `toksvig` gives the normal map of packed textures, for their mip
+    /// levels.
+    pub fn save(
+        &self,
+        image: DynamicImage,
+        path: &Path,
+        texture: Texture,
+        toksvig: Option<&Toksvig>,
+        output: &OutputOptions,
+    ) -> Result<()> {
+        let color_space = texture.color_space();
+        let written = self.output_colorspace.unwrap_or(color_space);
+
+        output.save(
+            &color::convert(image, color_space, written),
+            path,
+            Some(written),
+            Some(texture),
+            toksvig,
+        )
+    }
+}
@@ -0,0 +1,119 @@
+use crate::depth;
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use std::str::FromStr;
+
+/// A `Phong` smoothness of 1 stands for a Blinn-Phong specular exponent of
+/// `2^13 - 1`
+const MAX_PHONG_EXPONENT_LOG2: f32 = 13.0;
+
+/// How a smoothness value stored in a packed texture relates to the
+/// roughness value of a separate roughness map
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RoughnessCurve {
+    /// Smoothness is `1 - roughness`
+    #[default]
+    Linear,
+    /// Roughness maps hold the squared "alpha" roughness used by shading
+    /// models, while smoothness is `1 - sqrt(roughness)`, as in Unity
+    Squared,
+    /// Smoothness is a Blinn-Phong specular exponent, stored as
+    /// `log2(exponent + 1) / 13`, with the exponent matched to the
+    /// Beckmann roughness `2 / alpha^2 - 2`
+    Phong,
+}
+
+/// The command line names of every roughness curve
+pub const NAMES: &[&str] = &["linear", "squared", "perceptual", "phong", "beckmann"];
+
+impl RoughnessCurve {
+    /// Converts a roughness value from 0 to 1 to a smoothness value from 0
+    /// to 1
+    pub fn smoothness(self, roughness: f32) -> f32 {
+        let roughness = roughness.clamp(0.0, 1.0);
+
+        match self {
+            RoughnessCurve::Linear => 1.0 - roughness,
+            RoughnessCurve::Squared => 1.0 - roughness.sqrt(),
+            RoughnessCurve::Phong => {
+                let alpha = (roughness * roughness).max(f32::EPSILON);
+                let exponent = 2.0 / (alpha * alpha) - 2.0;
+
+                ((exponent + 1.0).log2() / MAX_PHONG_EXPONENT_LOG2).clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    /// Converts a smoothness value from 0 to 1 to a roughness value from 0
+    /// to 1, the reverse of [`RoughnessCurve::smoothness`]
+    pub fn roughness(self, smoothness: f32) -> f32 {
+        let smoothness = smoothness.clamp(0.0, 1.0);
+
+        match self {
+            RoughnessCurve::Linear => 1.0 - smoothness,
+            RoughnessCurve::Squared => (1.0 - smoothness) * (1.0 - smoothness),
+            RoughnessCurve::Phong => {
+                let exponent = (smoothness * MAX_PHONG_EXPONENT_LOG2).exp2() - 1.0;
+                let alpha = (2.0 / (exponent + 2.0)).sqrt();
+
+                alpha.sqrt()
+            }
+        }
+    }
+
+    /// Converts a roughness value from 0 to 1 to the "alpha" roughness used
+    /// by shading models
+    pub fn alpha(self, roughness: f32) -> f32 {
+        let roughness = roughness.clamp(0.0, 1.0);
+
+        match self {
+            RoughnessCurve::Squared => roughness,
+            RoughnessCurve::Linear | RoughnessCurve::Phong => roughness * roughness,
+        }
+    }
+
+    /// Converts an "alpha" roughness to a roughness value from 0 to 1, the
+    /// reverse of [`RoughnessCurve::alpha`]
+    pub fn roughness_from_alpha(self, alpha: f32) -> f32 {
+        let alpha = alpha.clamp(0.0, 1.0);
+
+        match self {
+            RoughnessCurve::Squared => alpha,
+            RoughnessCurve::Linear | RoughnessCurve::Phong => alpha.sqrt(),
+        }
+    }
+}
+
+impl FromStr for RoughnessCurve {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "linear" => Ok(RoughnessCurve::Linear),
+            "squared" | "perceptual" => Ok(RoughnessCurve::Squared),
+            "phong" | "beckmann" => Ok(RoughnessCurve::Phong),
+            _ => bail!(
+                "Unknown roughness curve {:?}, expected one of {}",
+                name,
+                NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+/// Applies a function to the colour channels of every pixel of an image,
+/// leaving alpha as-is
+///
+/// The function is given and returns values from 0 to 1. The remapped
+/// image is the same kind of image as the original, keeping its precision.
+pub fn remap(image: &DynamicImage, function: impl Fn(f32) -> f32) -> DynamicImage {
+    let mut remapped = image.to_rgba32f();
+
+    for pixel in remapped.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = function(*channel).clamp(0.0, 1.0);
+        }
+    }
+
+    depth::convert(DynamicImage::ImageRgba32F(remapped), image.color())
+}
@@ -0,0 +1,273 @@
+use crate::bc::{self, Block};
+use crate::color::ColorSpace;
+use crate::depth::BitDepth;
+use crate::layout::Map;
+use crate::texture_set::Texture;
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use rayon::prelude::*;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How the pixels in a DDS file are stored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Chosen from what the texture holds
+    #[default]
+    Auto,
+    /// Opaque colour, 4 bits per pixel
+    Bc1,
+    /// Colour and alpha, 8 bits per pixel
+    Bc3,
+    /// A single channel, 4 bits per pixel
+    Bc4,
+    /// Two channels, 8 bits per pixel, for normal maps
+    Bc5,
+    /// Colour and alpha at higher quality, 8 bits per pixel
+    Bc7,
+    /// Uncompressed 8-bit RGBA
+    Rgba,
+}
+
+/// The command line names of every DDS format
+pub const NAMES: &[&str] = &["auto", "bc1", "bc3", "bc4", "bc5", "bc7", "rgba"];
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Format::Auto),
+            "bc1" => Ok(Format::Bc1),
+            "bc3" => Ok(Format::Bc3),
+            "bc4" => Ok(Format::Bc4),
+            "bc5" => Ok(Format::Bc5),
+            "bc7" => Ok(Format::Bc7),
+            "rgba" => Ok(Format::Rgba),
+            _ => bail!(
+                "Unknown DDS format {:?}, expected one of {}",
+                name,
+                NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            Format::Auto => "automatic",
+            Format::Bc1 => "BC1",
+            Format::Bc3 => "BC3",
+            Format::Bc4 => "BC4",
+            Format::Bc5 => "BC5",
+            Format::Bc7 => "BC7",
+            Format::Rgba => "RGBA",
+        })
+    }
+}
+
+impl Format {
+    /// Chooses the format for a texture
+    ///
+    /// Single-channel maps such as roughness and metallic use BC4, normal
+    /// maps BC5, packed maps such as MetallicSmoothness and ORM BC7, and
+    /// colour maps BC1 unless they have alpha, when they use BC7. Textures
+    /// of unknown kind are chosen for by their channels, with greyscale
+    /// images which have alpha using BC7 so that their alpha is kept.
+    fn choose(texture: Option<Texture>, image: &DynamicImage) -> Format {
+        let alpha = image.color().has_alpha();
+
+        match texture {
+            Some(Texture::Packed(_)) => Format::Bc7,
+            Some(Texture::Map(Map::Normal)) => Format::Bc5,
+            Some(Texture::Map(map)) if map.color_space() == ColorSpace::Linear => Format::Bc4,
+            Some(Texture::Map(_)) if alpha => Format::Bc7,
+            Some(Texture::Map(_)) => Format::Bc1,
+            None => match image.color().channel_count() {
+                1 => Format::Bc4,
+                _ if alpha => Format::Bc7,
+                _ => Format::Bc1,
+            },
+        }
+    }
+
+    /// The DXGI format number, which depends on the colour space for
+    /// formats with an sRGB variant
+    fn dxgi_format(&self, color_space: ColorSpace) -> u32 {
+        let srgb = color_space == ColorSpace::Srgb;
+
+        match self {
+            Format::Bc1 => 71 + srgb as u32,
+            Format::Bc3 => 77 + srgb as u32,
+            Format::Bc4 => 80,
+            Format::Bc5 => 83,
+            Format::Bc7 => 98 + srgb as u32,
+            Format::Auto | Format::Rgba => 28 + srgb as u32,
+        }
+    }
+
+    /// The size of a 4x4 block of pixels, or of a pixel for uncompressed
+    /// formats
+    fn block_size(&self) -> usize {
+        match self {
+            Format::Bc1 | Format::Bc4 => 8,
+            Format::Auto | Format::Rgba => 4,
+            _ => 16,
+        }
+    }
+
+    fn is_compressed(&self) -> bool {
+        !matches!(self, Format::Auto | Format::Rgba)
+    }
+
+    /// Compresses a block of pixels
+    fn compress(&self, block: &Block) -> Vec<u8> {
+        match self {
+            Format::Bc1 => bc::bc1(block).to_vec(),
+            Format::Bc3 => bc::bc3(block).to_vec(),
+            Format::Bc4 => bc::bc4(block, 0).to_vec(),
+            Format::Bc5 => bc::bc5(block).to_vec(),
+            _ => bc::bc7(block).to_vec(),
+        }
+    }
+
+    /// The stored pixels of a mip level
+    ///
+    /// BC5 only stores red and green, so greyscale images with alpha are
+    /// stored with their alpha in green.
+    fn encode(&self, level: &DynamicImage) -> Vec<u8> {
+        let mut pixels = level.to_rgba8();
+
+        if *self == Format::Bc5 && level.color().channel_count() == 2 {
+            for pixel in pixels.pixels_mut() {
+                pixel[1] = pixel[3];
+            }
+        }
+
+        if !self.is_compressed() {
+            return pixels.into_raw();
+        }
+
+        bc::blocks(&pixels)
+            .par_iter()
+            .flat_map_iter(|block| self.compress(block))
+            .collect()
+    }
+}
+
+/// Encodes mip levels of a texture, largest first, as a DDS file
+///
+/// The format is chosen from what the texture holds unless one is given.
+/// Every format holds 8-bit values, so deeper images are reduced to 8 bits.
+pub fn encode(
+    levels: &[DynamicImage],
+    color_space: ColorSpace,
+    texture: Option<Texture>,
+    format: Format,
+) -> Result<Vec<u8>> {
+    let base = match levels.first() {
+        Some(base) => base,
+        None => bail!("There are no mip levels to write!"),
+    };
+
+    let depth = BitDepth::of(base);
+
+    if depth != BitDepth::Eight {
+        warn!(
+            "DDS files cannot hold {} images, reducing them to 8-bit",
+            depth
+        );
+    }
+
+    let format = match format {
+        Format::Auto => Format::choose(texture, base),
+        format => format,
+    };
+
+    debug!(
+        "Encoding {}x{} DDS texture as {}",
+        base.width(),
+        base.height(),
+        format
+    );
+
+    let data: Vec<Vec<u8>> = levels.iter().map(|level| format.encode(level)).collect();
+
+    // Caps, height, width, pixel format, mip map count, and either the
+    // linear size of compressed formats or the pitch of uncompressed ones
+    let mut flags = 0x1 | 0x2 | 0x4 | 0x1000 | 0x20000;
+    let mut caps: u32 = 0x1000;
+
+    let pitch_or_linear_size = if format.is_compressed() {
+        flags |= 0x80000;
+        data[0].len() as u32
+    } else {
+        flags |= 0x8;
+        base.width() * format.block_size() as u32
+    };
+
+    if levels.len() > 1 {
+        // Complex, mip map
+        caps |= 0x8 | 0x400000;
+    }
+
+    let mut file = b"DDS ".to_vec();
+
+    for value in [
+        124,
+        flags,
+        base.height(),
+        base.width(),
+        pitch_or_linear_size,
+        0,
+        levels.len() as u32,
+    ] {
+        file.extend(value.to_le_bytes());
+    }
+
+    file.extend([0; 4 * 11]);
+
+    // The pixel format only says that the DX10 header follows
+    file.extend(32_u32.to_le_bytes());
+    file.extend(0x4_u32.to_le_bytes());
+    file.extend(b"DX10");
+    file.extend([0; 4 * 5]);
+
+    file.extend(caps.to_le_bytes());
+    file.extend([0; 4 * 4]);
+
+    // The DX10 header: a single 2D texture, with straight alpha if it has
+    // alpha and opaque otherwise
+    let alpha_mode: u32 = if matches!(format, Format::Bc1 | Format::Bc4 | Format::Bc5) {
+        3
+    } else {
+        1
+    };
+
+    for value in [format.dxgi_format(color_space), 3, 0, 1, alpha_mode] {
+        file.extend(value.to_le_bytes());
+    }
+
+    for level in data {
+        file.extend(level);
+    }
+
+    Ok(file)
+}
+
+/// Writes mip levels of a texture to a DDS file, largest first, as
+/// [`encode`] encodes them
+pub fn save(
+    levels: &[DynamicImage],
+    path: &Path,
+    color_space: ColorSpace,
+    texture: Option<Texture>,
+    format: Format,
+) -> Result<()> {
+    fs::write(path, encode(levels, color_space, texture, format)?)?;
+
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Result};
+use image::{ColorType, DynamicImage, ImageFormat, Rgba32FImage};
+use std::fmt;
+
+/// How precisely each channel of an image is stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BitDepth {
+    /// 8-bit integers, as in most PNG, JPEG and TGA files
+    Eight,
+    /// 16-bit integers, as in 16-bit PNG and TIFF files
+    Sixteen,
+    /// 32-bit floats, as in EXR files
+    Float,
+}
+
+impl BitDepth {
+    /// The depth of an image's channels
+    pub fn of(image: &DynamicImage) -> Self {
+        match image.color() {
+            ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+                BitDepth::Sixteen
+            }
+            ColorType::Rgb32F | ColorType::Rgba32F => BitDepth::Float,
+            _ => BitDepth::Eight,
+        }
+    }
+
+    /// The most precise depth of any of the images, so that combining them
+    /// loses nothing
+    pub fn deepest(images: &[DynamicImage]) -> Self {
+        images
+            .iter()
+            .map(BitDepth::of)
+            .max()
+            .unwrap_or(BitDepth::Eight)
+    }
+
+    /// The kind of image with this depth and the given number of channels
+    ///
+    /// There are no greyscale float images, so float images with one or two
+    /// channels hold the grey value in red, green and blue.
+    pub fn color_type(self, channels: usize) -> ColorType {
+        match (self, channels) {
+            (BitDepth::Eight, 1) => ColorType::L8,
+            (BitDepth::Eight, 2) => ColorType::La8,
+            (BitDepth::Eight, 3) => ColorType::Rgb8,
+            (BitDepth::Eight, _) => ColorType::Rgba8,
+            (BitDepth::Sixteen, 1) => ColorType::L16,
+            (BitDepth::Sixteen, 2) => ColorType::La16,
+            (BitDepth::Sixteen, 3) => ColorType::Rgb16,
+            (BitDepth::Sixteen, _) => ColorType::Rgba16,
+            (BitDepth::Float, 1 | 3) => ColorType::Rgb32F,
+            (BitDepth::Float, _) => ColorType::Rgba32F,
+        }
+    }
+
+    /// Builds an image of this depth from `channels` values per pixel,
+    /// where 0 is black and 1 is white
+    pub fn image(
+        self,
+        width: u32,
+        height: u32,
+        channels: usize,
+        samples: &[f32],
+    ) -> Result<DynamicImage> {
+        let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+
+        for pixel in samples.chunks_exact(channels) {
+            match *pixel {
+                [grey] => rgba.extend([grey, grey, grey, 1.0]),
+                [grey, alpha] => rgba.extend([grey, grey, grey, alpha]),
+                [red, green, blue] => rgba.extend([red, green, blue, 1.0]),
+                [red, green, blue, alpha, ..] => rgba.extend([red, green, blue, alpha]),
+                _ => unreachable!(),
+            }
+        }
+
+        let image = Rgba32FImage::from_raw(width, height, rgba)
+            .ok_or_else(|| anyhow!("Could not create output image"))?;
+
+        Ok(convert(
+            DynamicImage::ImageRgba32F(image),
+            self.color_type(channels),
+        ))
+    }
+
+    /// Whether files in a format can hold images of this depth
+    fn supported_by(self, format: ImageFormat) -> bool {
+        match self {
+            BitDepth::Eight => format != ImageFormat::OpenExr,
+            BitDepth::Sixteen => matches!(
+                format,
+                ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Pnm
+            ),
+            BitDepth::Float => format == ImageFormat::OpenExr,
+        }
+    }
+}
+
+impl fmt::Display for BitDepth {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            BitDepth::Eight => "8-bit",
+            BitDepth::Sixteen => "16-bit",
+            BitDepth::Float => "float",
+        })
+    }
+}
+
+/// Converts an image to another kind of image, such as greyscale 16-bit
+pub fn convert(image: DynamicImage, color: ColorType) -> DynamicImage {
+    if image.color() == color {
+        return image;
+    }
+
+    match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(image.to_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(image.to_rgb8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(image.to_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(image.to_rgb16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(image.to_rgba16()),
+        ColorType::Rgb32F => DynamicImage::ImageRgb32F(image.to_rgb32f()),
+        ColorType::Rgba32F => DynamicImage::ImageRgba32F(image.to_rgba32f()),
+        _ => DynamicImage::ImageRgba8(image.to_rgba8()),
+    }
+}
+
+/// Converts an image to a depth files in `format` can hold, if they cannot
+/// hold its own depth
+///
+/// Formats which cannot hold float or 16-bit images get the most precise
+/// depth they can hold instead, with a warning, since precision is lost.
+pub fn for_format(image: DynamicImage, format: ImageFormat) -> DynamicImage {
+    let depth = BitDepth::of(&image);
+
+    if depth.supported_by(format) {
+        return image;
+    }
+
+    let target = [BitDepth::Float, BitDepth::Sixteen, BitDepth::Eight]
+        .into_iter()
+        .find(|target| target.supported_by(format))
+        .unwrap_or(BitDepth::Eight);
+
+    if target < depth {
+        warn!(
+            "{:?} files cannot hold {} images, reducing them to {}",
+            format, depth, target
+        );
+    }
+
+    let channels = image.color().channel_count() as usize;
+
+    convert(image, target.color_type(channels))
+}
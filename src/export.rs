@@ -0,0 +1,162 @@
+use crate::color::ColorSpace;
+use crate::mipmap::{self, Toksvig};
+use crate::resize::{self, PowerOfTwo};
+use crate::texture_set::Texture;
+use crate::{dds, image_file, ktx2};
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView};
+use std::path::Path;
+
+/// How texture files are written: their size, their mip levels, and how
+/// DDS files are compressed
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExportOptions {
+    /// Write every mip level down to 1x1 to files whose format can hold
+    /// them, such as KTX2 and DDS
+    pub mipmaps: bool,
+    /// How to encode the pixels of KTX2 files
+    pub ktx2_encoding: ktx2::Encoding,
+    /// How to store the pixels of DDS files
+    pub dds_format: dds::Format,
+    /// Do not widen the roughness or smoothness of packed textures in
+    /// smaller mip levels, or when they are shrunk
+    pub no_toksvig: bool,
+    /// The largest width or height to write files at
+    pub max_size: Option<u32>,
+    /// Which way to round the width and height of files to powers of two
+    pub power_of_two: Option<PowerOfTwo>,
+    /// How many times smaller to write files, at least 1
+    pub downscale: Option<f32>,
+}
+
+/// Whether a path has an extension, ignoring case
+fn has_extension(path: &Path, wanted: &str) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(wanted))
+}
+
+impl ExportOptions {
+    /// Whether files are resized
+    pub fn resizes(&self) -> bool {
+        self.downscale.is_some() || self.max_size.is_some() || self.power_of_two.is_some()
+    }
+
+    /// Whether mip levels, or textures shrunk by the options, are written
+    /// with roughness widened by the normal map, so that the normal map is
+    /// needed
+    pub fn toksvig(&self) -> bool {
+        (self.mipmaps || self.resizes()) && !self.no_toksvig
+    }
+
+    /// The size to write a texture of a size at, after shrinking it and
+    /// rounding it to powers of two as the options ask
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        let factor = self.downscale.unwrap_or(1.0);
+        let mut scaled = (width as f32 / factor, height as f32 / factor);
+
+        if let Some(max_size) = self.max_size {
+            let scale = (max_size as f32 / scaled.0.max(scaled.1)).min(1.0);
+
+            scaled = (scaled.0 * scale, scaled.1 * scale);
+        }
+
+        let (width, height) = (
+            (scaled.0.round() as u32).max(1),
+            (scaled.1.round() as u32).max(1),
+        );
+
+        match self.power_of_two {
+            Some(rounding) => (
+                rounding.round(width, self.max_size),
+                rounding.round(height, self.max_size),
+            ),
+            None => (width, height),
+        }
+    }
+
+    /// Writes an image to a file, in the format its extension asks for and
+    /// tagged with its colour space if it is known, giving back the image
+    /// as it was written
+    ///
+    /// The image is resized first if the options ask for a different size,
+    /// with a filter which suits what it holds. What the image holds, if it
+    /// is known, also decides how DDS files are compressed. The normal map
+    /// of a packed texture, if it is given, widens roughness in its mip
+    /// levels.
+    pub fn save(
+        &self,
+        image: &DynamicImage,
+        path: &Path,
+        color_space: Option<ColorSpace>,
+        texture: Option<Texture>,
+        toksvig: Option<&Toksvig>,
+    ) -> Result<DynamicImage> {
+        let roughness = match texture {
+            Some(Texture::Packed(layout)) => layout.roughness_channel(),
+            _ => None,
+        };
+
+        let toksvig = toksvig.filter(|_| !self.no_toksvig);
+
+        let (width, height) = self.size(image.width(), image.height());
+
+        let image = if image.dimensions() == (width, height) {
+            image.clone()
+        } else {
+            debug!(
+                "Resizing {}x{} image to {}x{} with {} filtering for {:?}",
+                image.width(),
+                image.height(),
+                width,
+                height,
+                resize::filter_for(texture),
+                path
+            );
+
+            let shrunk = resize::resize_texture(image, width, height, texture, color_space);
+
+            // The first level loses detail of the normal map too when it is
+            // shrunk, so it needs widening as much as smaller levels do
+            match (roughness, toksvig) {
+                (Some(roughness), Some(toksvig)) => mipmap::widen(&shrunk, roughness, toksvig),
+                _ => shrunk,
+            }
+        };
+
+        // The image crate cannot write KTX2 or DDS files
+        let ktx2 = has_extension(path, "ktx2");
+        let dds = has_extension(path, "dds");
+
+        if !ktx2 && !dds {
+            if self.mipmaps {
+                warn!("{:?} cannot hold mip levels, only writing the first", path);
+            }
+
+            image_file::save(&image, path, color_space)?;
+
+            return Ok(image);
+        }
+
+        if let Some(directory) = path.parent() {
+            if !directory.as_os_str().is_empty() {
+                std::fs::create_dir_all(directory)?;
+            }
+        }
+
+        let levels = if self.mipmaps {
+            mipmap::generate(&image, color_space, roughness, toksvig)
+        } else {
+            vec![image.clone()]
+        };
+
+        let color_space = color_space.unwrap_or(ColorSpace::Linear);
+
+        if dds {
+            dds::save(&levels, path, color_space, texture, self.dds_format)?;
+        } else {
+            ktx2::save(&levels, path, color_space, self.ktx2_encoding)?;
+        }
+
+        Ok(image)
+    }
+}
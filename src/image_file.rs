@@ -0,0 +1,489 @@
+use crate::color::{self, ColorSpace};
+use crate::depth::{self, BitDepth};
+use anyhow::{anyhow, bail, Context, Result};
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, ReadChannels,
+    ReadLayers, SmallVec, Text, WritableImage,
+};
+use image::codecs::hdr::HdrEncoder;
+use image::{ColorType, DynamicImage, ImageFormat, Rgb, Rgba32FImage};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Separates a layer of an EXR file from the file's path
+///
+/// A single layer of a multi-layer EXR file is given as `<file>#<layer>`,
+/// such as `Rusty.exr#Roughness`.
+pub const LAYER_SEPARATOR: char = '#';
+
+/// Splits a path such as `Rusty.exr#Roughness` into the path of the file
+/// and the name of the layer, if it has one
+pub fn split_layer(path: &Path) -> (PathBuf, Option<String>) {
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy(),
+        None => return (path.to_path_buf(), None),
+    };
+
+    match file_name.split_once(LAYER_SEPARATOR) {
+        Some((file_name, layer)) if is_exr(Path::new(file_name)) => {
+            (path.with_file_name(file_name), Some(layer.to_string()))
+        }
+        _ => (path.to_path_buf(), None),
+    }
+}
+
+/// The path of one layer of an EXR file
+pub fn with_layer(path: &Path, layer: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+
+    file_name.push(LAYER_SEPARATOR.to_string());
+    file_name.push(layer);
+
+    path.with_file_name(file_name)
+}
+
+/// The name of a file without its extension, followed by the name of its
+/// EXR layer if it has one, such as `Rusty_Roughness` for
+/// `Rusty.exr#Roughness`
+///
+/// Fails for paths which do not end in a file name, such as `..`.
+pub fn stem(path: &Path) -> Result<String> {
+    let (file, layer) = split_layer(path);
+
+    let stem = match file.file_stem() {
+        Some(stem) => stem.to_string_lossy().to_string(),
+        None => bail!("Could not determine the file name of {:?}!", path),
+    };
+
+    Ok(match layer {
+        Some(layer) => format!("{}_{}", stem, layer),
+        None => stem,
+    })
+}
+
+/// Whether a path is an image file which can be opened, or a layer of one
+pub fn is_image(path: &Path) -> bool {
+    let (file, _) = split_layer(path);
+
+    file.is_file() && ImageFormat::from_path(&file).is_ok()
+}
+
+fn is_exr(path: &Path) -> bool {
+    matches!(ImageFormat::from_path(path), Ok(ImageFormat::OpenExr))
+}
+
+/// Whether a file, or the file a layer is in, has a format which can only
+/// hold linear values, such as EXR and HDR files
+pub fn is_linear_format(path: &Path) -> bool {
+    let (file, _) = split_layer(path);
+
+    matches!(
+        ImageFormat::from_path(file),
+        Ok(ImageFormat::OpenExr | ImageFormat::Hdr)
+    )
+}
+
+/// Which channel of an image an EXR channel holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Component {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    Luminance,
+}
+
+/// Works out which layer an EXR channel belongs to and which of its
+/// channels it is, from names such as `R`, `roughness.Y` or `diffuse.R`
+///
+/// Channels which are not red, green, blue, alpha or luminance, such as
+/// `roughness` or `normal.Z`, are layers of their own, holding a greyscale
+/// image.
+fn classify(channel: &str) -> (String, Component) {
+    let (layer, name) = channel.rsplit_once('.').unwrap_or(("", channel));
+
+    let component = match name.to_ascii_uppercase().as_str() {
+        "R" | "RED" => Component::Red,
+        "G" | "GREEN" => Component::Green,
+        "B" | "BLUE" => Component::Blue,
+        "A" | "ALPHA" => Component::Alpha,
+        "Y" | "L" | "LUMINANCE" => Component::Luminance,
+        _ => return (channel.to_string(), Component::Luminance),
+    };
+
+    (layer.to_string(), component)
+}
+
+/// The layers of an EXR file, and the channels of each one as the part of
+/// the file and channel within it
+///
+/// Files can hold layers both as separate parts and as channels named like
+/// `roughness.Y` within one part, so both are turned into layers named like
+/// `<part>.<layer>`.
+fn layers_of(
+    parts: &[(Option<String>, Vec<String>)],
+) -> BTreeMap<String, Vec<(Component, usize, usize)>> {
+    let mut layers: BTreeMap<String, Vec<(Component, usize, usize)>> = BTreeMap::new();
+
+    for (part_index, (part_name, channels)) in parts.iter().enumerate() {
+        for (channel_index, channel) in channels.iter().enumerate() {
+            let (layer, component) = classify(channel);
+
+            let name = match (part_name.as_deref(), layer.is_empty()) {
+                (Some(part_name), true) => part_name.to_string(),
+                (Some(part_name), false) => format!("{}.{}", part_name, layer),
+                (None, _) => layer,
+            };
+
+            let layer = layers.entry(name).or_default();
+
+            if layer.iter().any(|(existing, _, _)| *existing == component) {
+                warn!("Ignoring EXR channel {:?}, which repeats another", channel);
+                continue;
+            }
+
+            layer.push((component, part_index, channel_index));
+        }
+    }
+
+    layers
+}
+
+/// Lists the layers of an EXR file, or nothing for other files
+///
+/// An EXR file with a single unnamed layer, as most are, also has no
+/// layers to choose between.
+pub fn layers(path: &Path) -> Result<Vec<String>> {
+    if !is_exr(path) {
+        return Ok(Vec::new());
+    }
+
+    let meta_data = exr::meta::MetaData::read_from_file(path, false)
+        .with_context(|| format!("Could not read {:?}", path))?;
+
+    let parts: Vec<_> = meta_data
+        .headers
+        .iter()
+        .map(|header| {
+            (
+                header
+                    .own_attributes
+                    .layer_name
+                    .as_ref()
+                    .map(Text::to_string),
+                header
+                    .channels
+                    .list
+                    .iter()
+                    .map(|channel| channel.name.to_string())
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let layers = layers_of(&parts);
+
+    if layers.len() == 1 && layers.contains_key("") {
+        return Ok(Vec::new());
+    }
+
+    Ok(layers.into_keys().collect())
+}
+
+/// Reads one layer of an EXR file, or its only layer if it has just one
+fn open_exr(path: &Path, layer: Option<&str>) -> Result<DynamicImage> {
+    let image = exr::prelude::read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .all_channels()
+        .all_layers()
+        .all_attributes()
+        .from_file(path)
+        .with_context(|| format!("Could not read {:?}", path))?;
+
+    let parts: Vec<_> = image
+        .layer_data
+        .iter()
+        .map(|part| {
+            (
+                part.attributes.layer_name.as_ref().map(Text::to_string),
+                part.channel_data
+                    .list
+                    .iter()
+                    .map(|channel| channel.name.to_string())
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let mut layers = layers_of(&parts);
+    let names = layers.keys().cloned().collect::<Vec<_>>().join(", ");
+
+    let channels = match layer {
+        Some(layer) => layers
+            .remove(layer)
+            .ok_or_else(|| anyhow!("{:?} has no layer {:?}, only {}", path, layer, names))?,
+        None if layers.len() == 1 => layers.into_values().next().unwrap_or_default(),
+        None => match layers.remove("") {
+            Some(channels) => channels,
+            None => bail!(
+                "{:?} has several layers, choose one with {:?}: {}",
+                path,
+                with_layer(path, "<layer>"),
+                names
+            ),
+        },
+    };
+
+    let (_, part_index, _) = channels
+        .first()
+        .ok_or_else(|| anyhow!("{:?} has no channels", path))?;
+    let part = &image.layer_data[*part_index];
+
+    let read = |component: Component| -> Option<Vec<f32>> {
+        channels
+            .iter()
+            .find(|(channel_component, _, _)| *channel_component == component)
+            .map(|(_, part_index, channel_index)| {
+                image.layer_data[*part_index].channel_data.list[*channel_index]
+                    .sample_data
+                    .values_as_f32()
+                    .collect()
+            })
+    };
+
+    let (width, height) = (part.size.width(), part.size.height());
+    let pixels = width * height;
+
+    let colour = [Component::Red, Component::Green, Component::Blue].map(read);
+    let alpha = read(Component::Alpha);
+
+    let [red, green, blue] = if colour.iter().any(Option::is_some) {
+        colour.map(|channel| channel.unwrap_or_else(|| vec![0.0; pixels]))
+    } else {
+        let luminance = read(Component::Luminance).unwrap_or_else(|| vec![0.0; pixels]);
+
+        [luminance.clone(), luminance.clone(), luminance]
+    };
+
+    if [&red, &green, &blue]
+        .iter()
+        .any(|channel| channel.len() != pixels)
+    {
+        bail!("The channels of {:?} are not the same size!", path);
+    }
+
+    let mut samples = Vec::with_capacity(pixels * 4);
+
+    for pixel in 0..pixels {
+        samples.extend([red[pixel], green[pixel], blue[pixel]]);
+
+        if let Some(alpha) = &alpha {
+            samples.push(alpha.get(pixel).copied().unwrap_or(1.0));
+        }
+    }
+
+    BitDepth::Float.image(
+        width as u32,
+        height as u32,
+        if alpha.is_some() { 4 } else { 3 },
+        &samples,
+    )
+}
+
+/// Opens an image file, or one layer of an EXR file
+///
+/// EXR files can have any number of channels and layers. Red, green, blue
+/// and alpha channels become a colour image. There are no greyscale float
+/// images, so a single luminance or other channel is read into the red,
+/// green and blue channels of an RGB one.
+pub fn open(path: impl AsRef<Path>) -> Result<DynamicImage> {
+    let (file, layer) = split_layer(path.as_ref());
+
+    if is_exr(&file) {
+        return open_exr(&file, layer.as_deref());
+    }
+
+    image::open(&file).with_context(|| format!("Could not open {:?}", file))
+}
+
+/// Writes an image to an EXR file, as 32-bit float channels
+///
+/// 8 and 16-bit greyscale images are written as a single luminance
+/// channel. Float images are always RGB or RGBA, so they are written as
+/// red, green and blue channels even if they hold greyscale maps. Files
+/// are written with a single layer, as writing multi-layer EXR files is
+/// not supported.
+fn save_exr(image: &DynamicImage, path: &Path) -> Result<()> {
+    let color = image.color();
+    let pixels: Rgba32FImage = image.to_rgba32f();
+    let size = (pixels.width() as usize, pixels.height() as usize);
+
+    let channel = |name: &str, index: usize| {
+        AnyChannel::new(
+            name,
+            FlatSamples::F32(pixels.pixels().map(|pixel| pixel[index]).collect()),
+        )
+    };
+
+    let mut channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = if color.has_color() {
+        SmallVec::from_vec(vec![channel("R", 0), channel("G", 1), channel("B", 2)])
+    } else {
+        SmallVec::from_vec(vec![channel("Y", 0)])
+    };
+
+    if color.has_alpha() {
+        channels.push(channel("A", 3));
+    }
+
+    let layer = Layer::new(
+        size,
+        LayerAttributes::default(),
+        Encoding::SMALL_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .with_context(|| format!("Could not write {:?}", path))?;
+
+    Ok(())
+}
+
+/// Writes an image to a Radiance HDR file, which has no alpha channel
+fn save_hdr(image: &DynamicImage, path: &Path) -> Result<()> {
+    let pixels: Vec<Rgb<f32>> = image.to_rgb32f().pixels().copied().collect();
+
+    HdrEncoder::new(BufWriter::new(File::create(path)?)).encode(
+        &pixels,
+        image.width() as usize,
+        image.height() as usize,
+    )?;
+
+    Ok(())
+}
+
+/// Writes an image to a PNG file, tagged with its colour space if it is
+/// known
+///
+/// sRGB images get an sRGB chunk, and linear images get a gAMA chunk of 1.0
+/// and an iCCP chunk with a linear profile, so that importers do not apply
+/// gamma to them.
+fn save_png(image: &DynamicImage, path: &Path, color_space: Option<ColorSpace>) -> Result<()> {
+    let image = depth::for_format(image.clone(), ImageFormat::Png);
+
+    let color_type = match image.color() {
+        ColorType::L8 | ColorType::L16 => png::ColorType::Grayscale,
+        ColorType::La8 | ColorType::La16 => png::ColorType::GrayscaleAlpha,
+        ColorType::Rgb8 | ColorType::Rgb16 => png::ColorType::Rgb,
+        _ => png::ColorType::Rgba,
+    };
+
+    let (bit_depth, data) = match BitDepth::of(&image) {
+        BitDepth::Sixteen => (
+            png::BitDepth::Sixteen,
+            // PNG files hold 16-bit values big-endian first
+            image
+                .as_bytes()
+                .chunks_exact(2)
+                .flat_map(|value| u16::from_ne_bytes([value[0], value[1]]).to_be_bytes())
+                .collect(),
+        ),
+        _ => (png::BitDepth::Eight, image.as_bytes().to_vec()),
+    };
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width(),
+        image.height(),
+    );
+
+    encoder.set_color(color_type);
+    encoder.set_depth(bit_depth);
+
+    match color_space {
+        Some(ColorSpace::Srgb) => encoder.set_srgb(png::SrgbRenderingIntent::Perceptual),
+        Some(ColorSpace::Linear) => encoder.set_source_gamma(png::ScaledFloat::new(1.0)),
+        None => {}
+    }
+
+    let mut writer = encoder.write_header()?;
+
+    if color_space == Some(ColorSpace::Linear) {
+        let mut iccp = b"Linear\0\0".to_vec();
+        iccp.extend(deflate::deflate_bytes_zlib(&color::linear_icc_profile(
+            image.color().has_color(),
+        )));
+
+        writer.write_chunk(png::chunk::iCCP, &iccp)?;
+    }
+
+    writer.write_image_data(&data)?;
+
+    Ok(())
+}
+
+/// Works out the colour space an image file is stored in, from what its
+/// format can hold or the chunks of a PNG file
+///
+/// An sRGB chunk wins over an iCCP chunk, which wins over a gAMA chunk, as
+/// the PNG specification asks. Returns nothing if the file does not say.
+pub fn color_space(path: &Path) -> Result<Option<ColorSpace>> {
+    let (file, _) = split_layer(path);
+
+    match ImageFormat::from_path(&file)? {
+        ImageFormat::OpenExr | ImageFormat::Hdr => Ok(Some(ColorSpace::Linear)),
+        ImageFormat::Png => {
+            let reader = png::Decoder::new(File::open(&file)?).read_info()?;
+            let info = reader.info();
+
+            if info.srgb.is_some() {
+                return Ok(Some(ColorSpace::Srgb));
+            }
+
+            if let Some(profile) = &info.icc_profile {
+                if let Some(color_space) = color::icc_color_space(profile) {
+                    return Ok(Some(color_space));
+                }
+            }
+
+            Ok(info.source_gamma.map(|gamma| {
+                if (gamma.into_value() - 1.0).abs() < 0.01 {
+                    ColorSpace::Linear
+                } else {
+                    ColorSpace::Srgb
+                }
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Writes an image to a file, creating the directory it goes in if needed
+///
+/// Images more precise than the file format can hold are reduced to the
+/// most precise depth it can hold. EXR files keep full float precision.
+/// PNG files are tagged with `color_space`, if it is given.
+pub fn save(image: &DynamicImage, path: &Path, color_space: Option<ColorSpace>) -> Result<()> {
+    if let Some(directory) = path.parent() {
+        if !directory.as_os_str().is_empty() {
+            std::fs::create_dir_all(directory)?;
+        }
+    }
+
+    let format = ImageFormat::from_path(path)?;
+
+    match format {
+        ImageFormat::OpenExr => save_exr(image, path),
+        ImageFormat::Hdr => save_hdr(image, path),
+        ImageFormat::Png => save_png(image, path, color_space),
+        _ => {
+            depth::for_format(image.clone(), format).save_with_format(path, format)?;
+
+            Ok(())
+        }
+    }
+}
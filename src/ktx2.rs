@@ -0,0 +1,453 @@
+use crate::color::ColorSpace;
+use crate::depth::{self, BitDepth};
+use crate::image_file;
+use anyhow::{bail, Result};
+use image::{ColorType, DynamicImage};
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The bytes every KTX2 file starts with
+const IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+
+/// How the texture data in a KTX2 file is encoded
+///
+/// UASTC and ETC1S files are encoded by `toktx` from KTX-Software, version
+/// 4 or later, which needs to be installed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Uncompressed pixels
+    #[default]
+    None,
+    /// Basis Universal's high quality UASTC encoding
+    Uastc,
+    /// Basis Universal's small ETC1S encoding
+    Etc1s,
+}
+
+/// The command line names of every KTX2 encoding
+pub const NAMES: &[&str] = &["none", "uastc", "etc1s"];
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Ok(Encoding::None),
+            "uastc" => Ok(Encoding::Uastc),
+            "etc1s" => Ok(Encoding::Etc1s),
+            _ => bail!(
+                "Unknown KTX2 encoding {:?}, expected one of {}",
+                name,
+                NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            Encoding::None => "uncompressed",
+            Encoding::Uastc => "UASTC",
+            Encoding::Etc1s => "ETC1S",
+        })
+    }
+}
+
+/// The Vulkan format of uncompressed pixels, and the number of channels and
+/// bytes per channel it has
+///
+/// Greyscale formats are only used for linear data, as sRGB images always
+/// have four channels.
+fn vk_format(color: ColorType, color_space: ColorSpace) -> (u32, usize, usize) {
+    let srgb = color_space == ColorSpace::Srgb;
+
+    match (color, srgb) {
+        (ColorType::L8, _) => (9, 1, 1),
+        (ColorType::La8, _) => (16, 2, 1),
+        (ColorType::Rgba8, false) => (37, 4, 1),
+        (ColorType::Rgba8, true) => (43, 4, 1),
+        (ColorType::L16, _) => (70, 1, 2),
+        (ColorType::La16, _) => (77, 2, 2),
+        (ColorType::Rgba16, _) => (91, 4, 2),
+        _ => (109, 4, 4),
+    }
+}
+
+/// Converts an image to one of the kinds of image which KTX2 files hold
+///
+/// Web and mobile GPUs have no three-channel formats, so RGB images get an
+/// opaque alpha channel. There are no 16-bit or float sRGB formats, so sRGB
+/// images are reduced to 8 bits, and single and two-channel sRGB formats
+/// are rarely supported and read as red rather than grey, so greyscale sRGB
+/// images are expanded to RGBA.
+fn supported(image: &DynamicImage, color_space: ColorSpace) -> DynamicImage {
+    let depth = match BitDepth::of(image) {
+        BitDepth::Eight => BitDepth::Eight,
+        depth if color_space == ColorSpace::Srgb => {
+            warn!(
+                "KTX2 files cannot hold {} sRGB images, reducing them to 8-bit",
+                depth
+            );
+
+            BitDepth::Eight
+        }
+        depth => depth,
+    };
+
+    let channels = match image.color() {
+        _ if depth == BitDepth::Float || color_space == ColorSpace::Srgb => 4,
+        ColorType::L8 | ColorType::L16 => 1,
+        ColorType::La8 | ColorType::La16 => 2,
+        _ => 4,
+    };
+
+    depth::convert(image.clone(), depth.color_type(channels))
+}
+
+/// Builds the data format descriptor, which says what each byte of a pixel
+/// holds
+fn data_format_descriptor(
+    channels: usize,
+    bytes: usize,
+    float: bool,
+    color_space: ColorSpace,
+) -> Vec<u8> {
+    let ids: &[u8] = match channels {
+        1 => &[0],
+        2 => &[0, 1],
+        _ => &[0, 1, 2, 15],
+    };
+
+    let block_size = 24 + 16 * ids.len();
+
+    let mut descriptor = Vec::with_capacity(4 + block_size);
+    descriptor.extend((4 + block_size as u32).to_le_bytes());
+
+    // Khronos basic descriptor block, version 2
+    descriptor.extend(0_u32.to_le_bytes());
+    descriptor.extend(2_u16.to_le_bytes());
+    descriptor.extend((block_size as u16).to_le_bytes());
+
+    // RGBSDA colour model, BT.709 primaries, transfer function, straight
+    // alpha
+    descriptor.push(1);
+    descriptor.push(1);
+    descriptor.push(match color_space {
+        ColorSpace::Linear => 1,
+        ColorSpace::Srgb => 2,
+    });
+    descriptor.push(0);
+
+    // A single pixel per texel block, in a single plane
+    descriptor.extend([0; 4]);
+    descriptor.push((channels * bytes) as u8);
+    descriptor.extend([0; 7]);
+
+    for (index, id) in ids.iter().enumerate() {
+        let mut channel_type = *id;
+
+        if float {
+            // Signed float
+            channel_type |= 0x80 | 0x40;
+        } else if *id == 15 && color_space == ColorSpace::Srgb {
+            // Alpha is linear even in sRGB formats
+            channel_type |= 0x10;
+        }
+
+        descriptor.extend(((index * bytes * 8) as u16).to_le_bytes());
+        descriptor.push((bytes * 8 - 1) as u8);
+        descriptor.push(channel_type);
+        descriptor.extend([0; 4]);
+
+        let (lower, upper) = if float {
+            ((-1.0_f32).to_bits(), 1.0_f32.to_bits())
+        } else {
+            (0, (1_u64 << (bytes * 8)) as u32 - 1)
+        };
+
+        descriptor.extend(lower.to_le_bytes());
+        descriptor.extend(upper.to_le_bytes());
+    }
+
+    descriptor
+}
+
+/// Builds the key and value data, which says what wrote the file
+fn key_value_data() -> Vec<u8> {
+    let mut entry = b"KTXwriter\0".to_vec();
+    entry.extend(concat!("matknife ", env!("CARGO_PKG_VERSION")).as_bytes());
+    entry.push(0);
+
+    let mut data = (entry.len() as u32).to_le_bytes().to_vec();
+    data.extend(entry);
+    data.resize(data.len().div_ceil(4) * 4, 0);
+
+    data
+}
+
+/// The pixels of an image as bytes, with `bytes` per channel stored
+/// little-endian as KTX2 files need
+fn little_endian(image: &DynamicImage, bytes: usize) -> Vec<u8> {
+    match bytes {
+        2 => image
+            .as_bytes()
+            .chunks_exact(2)
+            .flat_map(|value| u16::from_ne_bytes([value[0], value[1]]).to_le_bytes())
+            .collect(),
+        4 => image
+            .as_bytes()
+            .chunks_exact(4)
+            .flat_map(|value| {
+                u32::from_ne_bytes([value[0], value[1], value[2], value[3]]).to_le_bytes()
+            })
+            .collect(),
+        _ => image.as_bytes().to_vec(),
+    }
+}
+
+fn pad(data: &mut Vec<u8>, alignment: usize) {
+    data.resize(data.len().div_ceil(alignment) * alignment, 0);
+}
+
+/// Encodes mip levels of a texture, largest first, as an uncompressed KTX2
+/// file
+///
+/// The colour space decides whether the texture uses an sRGB or a linear
+/// format, so that viewers decode it correctly.
+fn encode_uncompressed(levels: &[DynamicImage], color_space: ColorSpace) -> Result<Vec<u8>> {
+    let levels: Vec<DynamicImage> = levels
+        .iter()
+        .map(|level| supported(level, color_space))
+        .collect();
+
+    let base = match levels.first() {
+        Some(base) => base,
+        None => bail!("There are no mip levels to write!"),
+    };
+
+    let float = BitDepth::of(base) == BitDepth::Float;
+    let (format, channels, bytes) = vk_format(base.color(), color_space);
+    let descriptor = data_format_descriptor(channels, bytes, float, color_space);
+    let key_values = key_value_data();
+
+    let header_size = 80 + 24 * levels.len();
+    let descriptor_offset = header_size;
+    let key_values_offset = descriptor_offset + descriptor.len();
+
+    let mut file = Vec::new();
+    file.extend(IDENTIFIER);
+
+    for value in [
+        format,
+        bytes as u32,
+        base.width(),
+        base.height(),
+        0,
+        0,
+        1,
+        levels.len() as u32,
+        0,
+    ] {
+        file.extend(value.to_le_bytes());
+    }
+
+    file.extend((descriptor_offset as u32).to_le_bytes());
+    file.extend((descriptor.len() as u32).to_le_bytes());
+    file.extend((key_values_offset as u32).to_le_bytes());
+    file.extend((key_values.len() as u32).to_le_bytes());
+    file.extend(0_u64.to_le_bytes());
+    file.extend(0_u64.to_le_bytes());
+
+    // The level index is filled in once the levels have been laid out
+    let level_index = file.len();
+    file.resize(header_size, 0);
+
+    file.extend(descriptor);
+    file.extend(key_values);
+
+    // Levels are stored smallest first, each aligned to a whole pixel and
+    // to 4 bytes
+    let alignment = match channels * bytes {
+        1 | 2 | 4 => 4,
+        size => size,
+    };
+
+    for (index, level) in levels.iter().enumerate().rev() {
+        pad(&mut file, alignment);
+
+        let data = little_endian(level, bytes);
+        let offset = file.len() as u64;
+        let length = data.len() as u64;
+
+        let entry = level_index + 24 * index;
+        file[entry..entry + 8].copy_from_slice(&offset.to_le_bytes());
+        file[entry + 8..entry + 16].copy_from_slice(&length.to_le_bytes());
+        file[entry + 16..entry + 24].copy_from_slice(&length.to_le_bytes());
+
+        file.extend(data);
+    }
+
+    Ok(file)
+}
+
+/// The command which encodes Basis Universal textures, `toktx` from
+/// KTX-Software unless the `MATKNIFE_TOKTX` environment variable gives
+/// another
+fn toktx() -> OsString {
+    env::var_os("MATKNIFE_TOKTX").unwrap_or_else(|| "toktx".into())
+}
+
+/// A directory of its own for the files of one encoding, removed when it
+/// is dropped
+struct ScratchDirectory(PathBuf);
+
+impl ScratchDirectory {
+    fn new() -> io::Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "matknife-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        fs::create_dir_all(&path)?;
+
+        Ok(ScratchDirectory(path))
+    }
+}
+
+impl Drop for ScratchDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Encodes mip levels of a texture, largest first, as a KTX2 file with
+/// Basis Universal's UASTC or ETC1S encoding
+///
+/// Basis Universal has no encoder written in Rust, so the levels are
+/// written to PNG files and encoded by `toktx`, which keeps them as they
+/// are rather than making mip levels of its own. Basis Universal only
+/// encodes 8-bit images.
+fn encode_basis(
+    levels: &[DynamicImage],
+    color_space: ColorSpace,
+    encoding: Encoding,
+) -> Result<Vec<u8>> {
+    let scratch = ScratchDirectory::new()?;
+
+    let mut level_paths = Vec::with_capacity(levels.len());
+
+    for (index, level) in levels.iter().enumerate() {
+        let level = match supported(level, color_space) {
+            level if BitDepth::of(&level) == BitDepth::Eight => level,
+            level => {
+                if index == 0 {
+                    warn!(
+                        "{} KTX2 files cannot hold {} images, reducing them to 8-bit",
+                        encoding,
+                        BitDepth::of(&level)
+                    );
+                }
+
+                let channels = level.color().channel_count() as usize;
+
+                depth::convert(level, BitDepth::Eight.color_type(channels))
+            }
+        };
+
+        let path = scratch.0.join(format!("level{}.png", index));
+
+        // The transfer function is given to toktx rather than read from tags
+        image_file::save(&level, &path, None)?;
+        level_paths.push(path);
+    }
+
+    let output = scratch.0.join("texture.ktx2");
+
+    let mut command = Command::new(toktx());
+
+    command
+        .arg("--t2")
+        .arg("--encode")
+        .arg(match encoding {
+            Encoding::Etc1s => "etc1s",
+            _ => "uastc",
+        })
+        .arg("--assign_oetf")
+        .arg(match color_space {
+            ColorSpace::Linear => "linear",
+            ColorSpace::Srgb => "srgb",
+        });
+
+    if levels.len() > 1 {
+        command
+            .arg("--mipmap")
+            .arg("--levels")
+            .arg(levels.len().to_string());
+    }
+
+    command.arg(&output).args(&level_paths);
+
+    debug!("Encoding {} KTX2 file with {:?}", encoding, command);
+
+    let result = match command.output() {
+        Ok(result) => result,
+        Err(error) => bail!(
+            "Writing {} KTX2 files needs toktx from KTX-Software, which could not be run ({}). Install it, set MATKNIFE_TOKTX to where it is, or use --ktx2-encoding none",
+            encoding,
+            error
+        ),
+    };
+
+    if !result.status.success() {
+        bail!(
+            "toktx could not encode the {} KTX2 file: {}",
+            encoding,
+            String::from_utf8_lossy(&result.stderr).trim()
+        );
+    }
+
+    Ok(fs::read(&output)?)
+}
+
+/// Encodes mip levels of a texture, largest first, as a KTX2 file
+///
+/// The colour space decides whether the texture uses an sRGB or a linear
+/// format, so that viewers decode it correctly. UASTC and ETC1S files are
+/// encoded by `toktx`, as [`Encoding`] describes.
+pub fn encode(
+    levels: &[DynamicImage],
+    color_space: ColorSpace,
+    encoding: Encoding,
+) -> Result<Vec<u8>> {
+    match encoding {
+        Encoding::None => encode_uncompressed(levels, color_space),
+        _ if levels.is_empty() => bail!("There are no mip levels to write!"),
+        _ => encode_basis(levels, color_space, encoding),
+    }
+}
+
+/// Writes mip levels of a texture to a KTX2 file, largest first, as
+/// [`encode`] encodes them
+pub fn save(
+    levels: &[DynamicImage],
+    path: &Path,
+    color_space: ColorSpace,
+    encoding: Encoding,
+) -> Result<()> {
+    fs::write(path, encode(levels, color_space, encoding)?)?;
+
+    Ok(())
+}
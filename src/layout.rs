@@ -0,0 +1,677 @@
+use crate::color::ColorSpace;
+use crate::curve::{self, RoughnessCurve};
+use crate::pack::{self, Channel, ChannelSource};
+use crate::resize::{self, Filter, Target};
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use std::fmt;
+
+/// A kind of texture map which is stored in its own file when unpacked
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Map {
+    Metallic,
+    Roughness,
+    Occlusion,
+    DetailMask,
+    BaseColor,
+    Diffuse,
+    SpecularGlossiness,
+    Normal,
+    Height,
+    Emission,
+}
+
+/// Every kind of texture map
+pub const MAPS: &[Map] = &[
+    Map::Metallic,
+    Map::Roughness,
+    Map::Occlusion,
+    Map::DetailMask,
+    Map::BaseColor,
+    Map::Diffuse,
+    Map::SpecularGlossiness,
+    Map::Normal,
+    Map::Height,
+    Map::Emission,
+];
+
+impl Map {
+    /// The suffix of file names holding this map, such as `Metallic` in
+    /// `RustyMetallic.png`
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Map::Metallic => "Metallic",
+            Map::Roughness => "Roughness",
+            Map::Occlusion => "Occlusion",
+            Map::DetailMask => "DetailMask",
+            Map::BaseColor => "BaseColor",
+            Map::Diffuse => "Diffuse",
+            Map::SpecularGlossiness => "SpecularGlossiness",
+            Map::Normal => "Normal",
+            Map::Height => "Height",
+            Map::Emission => "Emission",
+        }
+    }
+
+    /// The name of the map on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Map::Metallic => "metallic",
+            Map::Roughness => "roughness",
+            Map::Occlusion => "occlusion",
+            Map::DetailMask => "detail-mask",
+            Map::BaseColor => "base-color",
+            Map::Diffuse => "diffuse",
+            Map::SpecularGlossiness => "specular-glossiness",
+            Map::Normal => "normal",
+            Map::Height => "height",
+            Map::Emission => "emission",
+        }
+    }
+
+    /// The colour space this map is usually stored in
+    pub fn color_space(self) -> ColorSpace {
+        match self {
+            Map::BaseColor | Map::Diffuse | Map::SpecularGlossiness | Map::Emission => {
+                ColorSpace::Srgb
+            }
+            _ => ColorSpace::Linear,
+        }
+    }
+
+    /// The value to use for this map when there is no file for it
+    pub fn default_value(self) -> u8 {
+        match self {
+            Map::Metallic | Map::SpecularGlossiness | Map::Emission => 0x00,
+            Map::Roughness | Map::Occlusion | Map::DetailMask | Map::BaseColor | Map::Diffuse => {
+                0xff
+            }
+            Map::Normal | Map::Height => 0x80,
+        }
+    }
+
+    /// A short description of what the map holds
+    pub fn description(self) -> &'static str {
+        match self {
+            Map::Metallic => "metallic",
+            Map::Roughness => "roughness",
+            Map::Occlusion => "occlusion",
+            Map::DetailMask => "detail mask",
+            Map::BaseColor => "base colour",
+            Map::Diffuse => "diffuse",
+            Map::SpecularGlossiness => "specular+glossiness",
+            Map::Normal => "normal",
+            Map::Height => "height",
+            Map::Emission => "emission",
+        }
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(self.description())
+    }
+}
+
+/// How several maps are packed into the channels of one texture
+#[derive(Debug)]
+pub struct Layout {
+    /// The name of the layout on the command line
+    pub name: &'static str,
+    /// A short description of the packed texture
+    pub description: &'static str,
+    /// The prefix of file names holding the packed texture
+    pub prefix: &'static str,
+    /// The suffix of file names holding the packed texture
+    pub suffix: &'static str,
+    /// The separate maps which are packed together, in the order the
+    /// inputs of `packed` refer to them
+    pub maps: &'static [Map],
+    /// The channels of the packed texture
+    pub packed: &'static [ChannelSource],
+    /// The channels of each separate map, read from the packed texture
+    pub unpacked: &'static [(Map, ChannelSource)],
+}
+
+/// Where to read smoothness from when splitting packed textures which store
+/// it, instead of the channel the layout stores it in
+#[derive(Clone, Debug, Default)]
+pub struct SplitOptions {
+    /// The smoothness, from 0 to 1, to use everywhere in packed textures
+    /// without the channel the layout stores smoothness in
+    pub default_smoothness: Option<f32>,
+    /// The channel to read smoothness from, of `smoothness_image` if it is
+    /// given, or of the packed texture otherwise
+    pub smoothness_channel: Option<Channel>,
+    /// An image to read smoothness from, rather than the packed texture,
+    /// from its luminance unless `smoothness_channel` says otherwise
+    pub smoothness_image: Option<DynamicImage>,
+    /// The channel to read metallic from, rather than the one the layout
+    /// stores it in
+    pub metallic_channel: Option<Channel>,
+}
+
+impl SplitOptions {
+    /// Whether any option says where else to read smoothness from
+    fn reads_smoothness(&self) -> bool {
+        self.default_smoothness.is_some()
+            || self.smoothness_channel.is_some()
+            || self.smoothness_image.is_some()
+    }
+
+    /// Fails if the options cannot be used to split textures packed with
+    /// `layout`
+    pub fn check(&self, layout: &Layout) -> Result<()> {
+        if let Some(smoothness) = self.default_smoothness {
+            if !(0.0..=1.0).contains(&smoothness) {
+                bail!("Default smoothness must be from 0 to 1, not {}", smoothness);
+            }
+        }
+
+        if self.reads_smoothness() && !matches!(layout.roughness_channel(), Some((_, true))) {
+            bail!(
+                "The {} layout stores roughness rather than smoothness, so smoothness options do not apply to it",
+                layout.name
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// How separate maps are read, and matched in size, when merging them
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MergeOptions {
+    /// The channel of metallic images to read metallic from, rather than
+    /// red
+    pub metallic_channel: Option<Channel>,
+    /// The channel of roughness images to read roughness from, rather than
+    /// red
+    pub roughness_channel: Option<Channel>,
+    /// The size to resize images to when they are not all the same size
+    pub resize_to: Option<Target>,
+    /// How to blend pixels when resizing images
+    pub resize_filter: Filter,
+}
+
+impl MergeOptions {
+    /// Reads a separate map from the channel of its image the options say
+    /// to, warning if the image is not greyscale and no channel was given
+    fn greyscale(&self, map: Map, image: DynamicImage) -> Result<DynamicImage> {
+        let (channel, option) = match map {
+            Map::Metallic => (self.metallic_channel, "--metallic-channel"),
+            Map::Roughness => (self.roughness_channel, "--roughness-channel"),
+            _ => (None, ""),
+        };
+
+        if let Some(channel) = channel {
+            return pack::pack(&[image], &[ChannelSource::input(0, channel)]);
+        }
+
+        if !pack::is_greyscale(&image) {
+            if option.is_empty() {
+                warn!(
+                    "The {} image is not greyscale, reading {} from its red channel",
+                    map, map
+                );
+            } else {
+                warn!(
+                    "The {} image is not greyscale, reading {} from its red channel. Use {} to choose another",
+                    map, map, option
+                );
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+/// Copies the first of `inputs`, with one of its channels read from
+/// `source` instead
+fn replace_channel(
+    inputs: &[DynamicImage],
+    channel: usize,
+    source: ChannelSource,
+) -> Result<DynamicImage> {
+    let mut sources = [Channel::Red, Channel::Green, Channel::Blue, Channel::Alpha]
+        .map(|channel| ChannelSource::input(0, channel));
+    sources[channel] = source;
+
+    pack::pack(inputs, &sources)
+}
+
+/// Unity's Standard shader `MetallicSmoothness` texture, with metallic in
+/// the colour channels and smoothness in alpha
+pub const UNITY: Layout = Layout {
+    name: "unity",
+    description: "metallic+smoothness",
+    prefix: "",
+    suffix: "MetallicSmoothness",
+    maps: &[Map::Metallic, Map::Roughness],
+    packed: &[
+        ChannelSource::input(0, Channel::Red),
+        ChannelSource::input(0, Channel::Red),
+        ChannelSource::input(0, Channel::Red),
+        ChannelSource::inverted(1, Channel::Red),
+    ],
+    unpacked: &[
+        (Map::Metallic, ChannelSource::input(0, Channel::Red)),
+        (Map::Roughness, ChannelSource::inverted(0, Channel::Alpha)),
+    ],
+};
+
+/// Unity's High Definition Render Pipeline `MaskMap` texture, with
+/// metallic in red, occlusion in green, detail mask in blue and smoothness
+/// in alpha
+pub const HDRP: Layout = Layout {
+    name: "hdrp",
+    description: "mask map",
+    prefix: "",
+    suffix: "MaskMap",
+    maps: &[
+        Map::Metallic,
+        Map::Occlusion,
+        Map::DetailMask,
+        Map::Roughness,
+    ],
+    packed: &[
+        ChannelSource::input(0, Channel::Red),
+        ChannelSource::input(1, Channel::Red),
+        ChannelSource::input(2, Channel::Red),
+        ChannelSource::inverted(3, Channel::Red),
+    ],
+    unpacked: &[
+        (Map::Metallic, ChannelSource::input(0, Channel::Red)),
+        (Map::Occlusion, ChannelSource::input(0, Channel::Green)),
+        (Map::DetailMask, ChannelSource::input(0, Channel::Blue)),
+        (Map::Roughness, ChannelSource::inverted(0, Channel::Alpha)),
+    ],
+};
+
+/// glTF 2.0's `metallicRoughnessTexture`, with roughness in green and
+/// metallic in blue
+///
+/// glTF ignores the red channel, so it is filled with white.
+pub const GLTF: Layout = Layout {
+    name: "gltf",
+    description: "metallic+roughness",
+    prefix: "",
+    suffix: "MetallicRoughness",
+    maps: &[Map::Metallic, Map::Roughness],
+    packed: &[
+        ChannelSource::Constant(0xff),
+        ChannelSource::input(1, Channel::Red),
+        ChannelSource::input(0, Channel::Red),
+    ],
+    unpacked: &[
+        (Map::Metallic, ChannelSource::input(0, Channel::Blue)),
+        (Map::Roughness, ChannelSource::input(0, Channel::Green)),
+    ],
+};
+
+/// glTF 2.0's `metallicRoughnessTexture` shared with `occlusionTexture`,
+/// with occlusion in red, roughness in green and metallic in blue
+pub const GLTF_ORM: Layout = Layout {
+    name: "gltf-orm",
+    description: "occlusion+roughness+metallic",
+    prefix: "",
+    suffix: "OcclusionRoughnessMetallic",
+    maps: &[Map::Occlusion, Map::Roughness, Map::Metallic],
+    packed: &[
+        ChannelSource::input(0, Channel::Red),
+        ChannelSource::input(1, Channel::Red),
+        ChannelSource::input(2, Channel::Red),
+    ],
+    unpacked: &[
+        (Map::Occlusion, ChannelSource::input(0, Channel::Red)),
+        (Map::Roughness, ChannelSource::input(0, Channel::Green)),
+        (Map::Metallic, ChannelSource::input(0, Channel::Blue)),
+    ],
+};
+
+/// Unreal Engine's `T_Name_ORM` texture, with occlusion in red, roughness
+/// in green and metallic in blue
+pub const UNREAL_ORM: Layout = Layout {
+    name: "unreal-orm",
+    description: "occlusion+roughness+metallic",
+    prefix: "T_",
+    suffix: "_ORM",
+    maps: &[Map::Occlusion, Map::Roughness, Map::Metallic],
+    packed: &[
+        ChannelSource::input(0, Channel::Red),
+        ChannelSource::input(1, Channel::Red),
+        ChannelSource::input(2, Channel::Red),
+    ],
+    unpacked: &[
+        (Map::Occlusion, ChannelSource::input(0, Channel::Red)),
+        (Map::Roughness, ChannelSource::input(0, Channel::Green)),
+        (Map::Metallic, ChannelSource::input(0, Channel::Blue)),
+    ],
+};
+
+/// Unreal Engine's `T_Name_RMA` texture, with roughness in red, metallic
+/// in green and occlusion in blue
+pub const UNREAL_RMA: Layout = Layout {
+    name: "unreal-rma",
+    description: "roughness+metallic+occlusion",
+    prefix: "T_",
+    suffix: "_RMA",
+    maps: &[Map::Roughness, Map::Metallic, Map::Occlusion],
+    packed: &[
+        ChannelSource::input(0, Channel::Red),
+        ChannelSource::input(1, Channel::Red),
+        ChannelSource::input(2, Channel::Red),
+    ],
+    unpacked: &[
+        (Map::Roughness, ChannelSource::input(0, Channel::Red)),
+        (Map::Metallic, ChannelSource::input(0, Channel::Green)),
+        (Map::Occlusion, ChannelSource::input(0, Channel::Blue)),
+    ],
+};
+
+/// Every known layout
+pub const LAYOUTS: &[&Layout] = &[&UNITY, &HDRP, &GLTF, &GLTF_ORM, &UNREAL_ORM, &UNREAL_RMA];
+
+/// The command line names of every known layout
+pub const NAMES: &[&str] = &[
+    UNITY.name,
+    HDRP.name,
+    GLTF.name,
+    GLTF_ORM.name,
+    UNREAL_ORM.name,
+    UNREAL_RMA.name,
+];
+
+/// Finds a layout by its command line name
+pub fn find(name: &str) -> Result<&'static Layout> {
+    match LAYOUTS.iter().find(|layout| layout.name == name) {
+        Some(layout) => Ok(layout),
+        None => bail!(
+            "Unknown layout {:?}, expected one of {}",
+            name,
+            LAYOUTS
+                .iter()
+                .map(|layout| layout.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+impl Layout {
+    /// Whether the separate maps can only be read from a packed texture
+    /// which has an alpha channel
+    pub fn needs_alpha(&self) -> bool {
+        self.unpacked.iter().any(|(_, source)| {
+            matches!(
+                source,
+                ChannelSource::Input {
+                    channel: Channel::Alpha,
+                    ..
+                }
+            )
+        })
+    }
+
+    /// The channel of the packed texture which a map is read from, and
+    /// whether it is stored inverted
+    pub fn map_channel(&self, map: Map) -> Option<(usize, bool)> {
+        self.unpacked
+            .iter()
+            .find_map(|(unpacked_map, source)| match source {
+                ChannelSource::Input {
+                    channel, invert, ..
+                } if *unpacked_map == map => Some((channel.index()?, *invert)),
+                _ => None,
+            })
+    }
+
+    /// The channel of the packed texture which holds roughness, and whether
+    /// it is stored as smoothness
+    pub fn roughness_channel(&self) -> Option<(usize, bool)> {
+        self.map_channel(Map::Roughness)
+    }
+
+    /// Every channel of the packed texture which a map is written to,
+    /// which is more than one for metallic in Unity's layout
+    pub fn packed_channels(&self, map: Map) -> Vec<usize> {
+        let input = self.maps.iter().position(|packed_map| *packed_map == map);
+
+        self.packed
+            .iter()
+            .enumerate()
+            .filter(|(_, source)| {
+                matches!(source, ChannelSource::Input { index, .. } if Some(*index) == input)
+            })
+            .map(|(channel, _)| channel)
+            .collect()
+    }
+
+    /// Puts smoothness in the channel of a packed texture the layout stores
+    /// it in, reading it from wherever the options say to
+    ///
+    /// Packed textures are left as they are if smoothness is to be read
+    /// from them as usual.
+    fn with_smoothness(&self, image: DynamicImage, options: &SplitOptions) -> Result<DynamicImage> {
+        let channel = match self.roughness_channel() {
+            Some((channel, true)) => channel,
+            _ => return Ok(image),
+        };
+
+        let missing = channel == 3 && !image.color().has_alpha();
+
+        let source = match (&options.smoothness_image, options.smoothness_channel) {
+            (Some(smoothness_image), smoothness_channel) => {
+                if smoothness_channel == Some(Channel::Alpha)
+                    && !smoothness_image.color().has_alpha()
+                {
+                    bail!("The smoothness file has no alpha channel to read smoothness from!");
+                }
+
+                let source = ChannelSource::input(1, smoothness_channel.unwrap_or(Channel::Luma));
+
+                return replace_channel(&[image, smoothness_image.clone()], channel, source);
+            }
+            (None, Some(Channel::Alpha)) if !image.color().has_alpha() => bail!(
+                "The texture has no alpha channel to read smoothness from! Use --default-smoothness, another --smoothness-channel or --smoothness-file to give it"
+            ),
+            (None, Some(smoothness_channel)) => ChannelSource::input(0, smoothness_channel),
+            (None, None) => match options.default_smoothness {
+                Some(smoothness) if missing => {
+                    warn!(
+                        "The texture has no alpha channel, using a smoothness of {} everywhere",
+                        smoothness
+                    );
+
+                    ChannelSource::Constant((smoothness * 255.0).round() as u8)
+                }
+                _ if missing => bail!(
+                    "The texture has no alpha channel for smoothness! Use --default-smoothness, --smoothness-channel or --smoothness-file to give it"
+                ),
+                _ => return Ok(image),
+            },
+        };
+
+        replace_channel(&[image], channel, source)
+    }
+
+    /// Reads metallic from the channel of a packed texture the options say
+    /// to, warning if the channels the layout stores it in do not agree
+    fn with_metallic(&self, image: DynamicImage, options: &SplitOptions) -> Result<DynamicImage> {
+        let channel = match self.map_channel(Map::Metallic) {
+            Some((channel, _)) => channel,
+            None => return Ok(image),
+        };
+
+        match options.metallic_channel {
+            Some(metallic_channel) => {
+                replace_channel(&[image], channel, ChannelSource::input(0, metallic_channel))
+            }
+            None => {
+                if self.packed_channels(Map::Metallic).len() > 1 && !pack::is_greyscale(&image) {
+                    warn!(
+                        "The texture is not greyscale, reading metallic from its red channel. Use --metallic-channel to choose another"
+                    );
+                }
+
+                Ok(image)
+            }
+        }
+    }
+
+    /// Reads each separate map out of a packed texture
+    ///
+    /// Roughness stored as smoothness is converted using `curve`.
+    pub fn split(
+        &self,
+        image: DynamicImage,
+        curve: RoughnessCurve,
+    ) -> Result<Vec<(Map, DynamicImage)>> {
+        self.split_with(image, curve, &SplitOptions::default())
+    }
+
+    /// Reads each separate map out of a packed texture, as the options say
+    ///
+    /// Roughness stored as smoothness is converted using `curve`.
+    pub fn split_with(
+        &self,
+        image: DynamicImage,
+        curve: RoughnessCurve,
+        options: &SplitOptions,
+    ) -> Result<Vec<(Map, DynamicImage)>> {
+        options.check(self)?;
+
+        // Smoothness comes first, as reading metallic from another channel
+        // gives packed textures without alpha an opaque one
+        let image = self.with_smoothness(image, options)?;
+        let image = self.with_metallic(image, options)?;
+
+        if self.needs_alpha() && !image.color().has_alpha() {
+            bail!("Input image does not have an alpha channel!");
+        }
+
+        let inputs = [image];
+
+        self.unpacked
+            .iter()
+            .map(|(map, source)| match (map, *source) {
+                (
+                    Map::Roughness,
+                    ChannelSource::Input {
+                        index,
+                        channel,
+                        invert: true,
+                    },
+                ) => {
+                    let smoothness = pack::pack(&inputs, &[ChannelSource::input(index, channel)])?;
+
+                    let roughness = curve::remap(&smoothness, |value| curve.roughness(value));
+
+                    Ok((*map, roughness))
+                }
+                _ => Ok((*map, pack::pack(&inputs, &[*source])?)),
+            })
+            .collect()
+    }
+
+    /// Packs separate maps into one texture
+    ///
+    /// `images` must be in the same order as `maps`. Maps without an image
+    /// are filled with their default value. Roughness stored as smoothness
+    /// is converted using `curve`.
+    pub fn merge(
+        &self,
+        images: Vec<Option<DynamicImage>>,
+        curve: RoughnessCurve,
+    ) -> Result<DynamicImage> {
+        self.merge_with(images, curve, &MergeOptions::default())
+    }
+
+    /// Packs separate maps into one texture, reading and resizing them as
+    /// the options say
+    ///
+    /// `images` must be in the same order as `maps`. Maps without an image
+    /// are filled with their default value. Roughness stored as smoothness
+    /// is converted using `curve`.
+    pub fn merge_with(
+        &self,
+        images: Vec<Option<DynamicImage>>,
+        curve: RoughnessCurve,
+        options: &MergeOptions,
+    ) -> Result<DynamicImage> {
+        if images.len() != self.maps.len() {
+            bail!(
+                "The {} layout needs {} images, not {}",
+                self.name,
+                self.maps.len(),
+                images.len()
+            );
+        }
+
+        let images = self
+            .maps
+            .iter()
+            .zip(images)
+            .map(|(map, image)| {
+                image
+                    .map(|image| options.greyscale(*map, image))
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let images =
+            resize::same_size(images, self.maps, options.resize_to, options.resize_filter)?;
+
+        // Inputs which are present move down to fill the gaps left by
+        // missing ones, so sources need to point at their new positions
+        let mut positions = Vec::with_capacity(images.len());
+        let mut inputs = Vec::with_capacity(images.len());
+
+        for image in images {
+            positions.push(image.as_ref().map(|_| inputs.len()));
+            inputs.extend(image);
+        }
+
+        let smoothness_index = self.maps.iter().position(|map| *map == Map::Roughness);
+        let smoothness_input = smoothness_index.and_then(|index| positions[index]);
+
+        // Smoothness is read from a copy of the roughness image which has
+        // already been converted, rather than by simply inverting it
+        if let Some(input) = smoothness_input {
+            inputs.push(curve::remap(&inputs[input], |value| {
+                curve.smoothness(value)
+            }));
+        }
+
+        let sources: Vec<ChannelSource> = self
+            .packed
+            .iter()
+            .map(|source| match *source {
+                ChannelSource::Input {
+                    index,
+                    channel,
+                    invert,
+                } => match positions[index] {
+                    Some(_) if invert && Some(index) == smoothness_index => ChannelSource::Input {
+                        index: inputs.len() - 1,
+                        channel,
+                        invert: false,
+                    },
+                    Some(index) => ChannelSource::Input {
+                        index,
+                        channel,
+                        invert,
+                    },
+                    None => {
+                        let value = self.maps[index].default_value();
+
+                        ChannelSource::Constant(if invert { 0xff - value } else { value })
+                    }
+                },
+                constant => constant,
+            })
+            .collect();
+
+        pack::pack(&inputs, &sources)
+    }
+}
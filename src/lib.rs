@@ -0,0 +1,53 @@
+//! Converts physically based rendering textures between the layouts and
+//! conventions of different engines and file formats, in memory
+//!
+//! Packed textures are split into separate maps and merged back with
+//! [`Layout::split`] and [`Layout::merge`], whole texture sets are
+//! converted between conventions with [`texture_set::convert_maps`], and
+//! normal maps with [`normal::convert`]. Images are [`image::DynamicImage`]
+//! values, so they can come from and go to anywhere; [`image_file`] reads
+//! and writes them as files, and [`ktx2`] and [`dds`] encode them, with
+//! mip levels from [`mipmap`], as GPU texture files. [`resize`] resizes
+//! images with filters suited to what they hold, and [`export`] writes
+//! textures at the size and in the format asked for.
+//!
+//! ```no_run
+//! use matknife::{layout, Map, RoughnessCurve};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let packed = image::open("RustyMetallicSmoothness.png")?;
+//!
+//! for (map, image) in layout::UNITY.split(packed, RoughnessCurve::Linear)? {
+//!     if map == Map::Roughness {
+//!         image.save("RustyRoughness.png")?;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+#[macro_use]
+extern crate log;
+
+mod bc;
+pub mod color;
+pub mod curve;
+pub mod dds;
+pub mod depth;
+pub mod export;
+pub mod image_file;
+pub mod ktx2;
+pub mod layout;
+pub mod lint;
+pub mod mipmap;
+pub mod normal;
+pub mod pack;
+pub mod resize;
+pub mod texture_set;
+pub mod workflow;
+
+pub use color::ColorSpace;
+pub use curve::RoughnessCurve;
+pub use layout::{Layout, Map};
+pub use normal::NormalConvention;
+pub use texture_set::{Convention, Texture};
@@ -0,0 +1,212 @@
+use crate::color;
+use crate::curve::RoughnessCurve;
+use crate::layout::Map;
+use crate::texture_set::Texture;
+use image::DynamicImage;
+use std::fmt;
+
+/// The range of sRGB values, out of 255, which real materials' albedo
+/// stays within
+///
+/// Charcoal is about the darkest material there is, and fresh snow the
+/// brightest.
+pub const ALBEDO_RANGE: (f32, f32) = (30.0, 240.0);
+
+/// The range of metallic values which are neither metal nor dielectric
+pub const PARTLY_METALLIC_RANGE: (f32, f32) = (0.2, 0.8);
+
+/// How serious a problem with a texture is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The texture will probably look wrong
+    Warning,
+    /// The texture will look wrong, or cannot be used at all
+    Error,
+}
+
+impl Severity {
+    /// The name of the severity in reports
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.pad(self.name())
+    }
+}
+
+/// A problem found with a texture
+#[derive(Clone, Debug, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// The severity of a problem affecting `fraction` of a texture's pixels
+///
+/// A few pixels are often out of range from filtering or compression, so
+/// they are not a problem. It is an error if most pixels are.
+fn severity(fraction: f32, tolerance: f32) -> Option<Severity> {
+    if fraction > 0.5 {
+        Some(Severity::Error)
+    } else if fraction > tolerance {
+        Some(Severity::Warning)
+    } else {
+        None
+    }
+}
+
+/// Checks the values of one map, which is in its usual colour space
+///
+/// `from` says where the map came from, for packed textures.
+fn check_map(map: Map, image: &DynamicImage, from: Option<&str>, issues: &mut Vec<Issue>) {
+    let pixels = image.to_rgba32f();
+    let has_alpha = image.color().has_alpha();
+
+    let mut push = |severity: Severity, message: String| {
+        issues.push(Issue {
+            severity,
+            message: match from {
+                Some(from) => format!("{} {}", from, message),
+                None => message,
+            },
+        })
+    };
+
+    match map {
+        Map::BaseColor | Map::Diffuse => {
+            // Fully transparent pixels are cut out, so their colour is never
+            // seen
+            let luminances: Vec<f32> = pixels
+                .pixels()
+                .filter(|pixel| !has_alpha || pixel[3] > 0.0)
+                .map(|pixel| {
+                    let [red, green, blue] =
+                        [pixel[0], pixel[1], pixel[2]].map(color::srgb_to_linear);
+
+                    color::linear_to_srgb(0.2126 * red + 0.7152 * green + 0.0722 * blue) * 255.0
+                })
+                .collect();
+
+            let count = luminances.len().max(1) as f32;
+            let (darkest, brightest) = ALBEDO_RANGE;
+
+            let dark = luminances.iter().filter(|&&value| value < darkest).count() as f32 / count;
+            let bright = luminances
+                .iter()
+                .filter(|&&value| value > brightest)
+                .count() as f32
+                / count;
+
+            if let Some(severity) = severity(dark, 0.01) {
+                push(
+                    severity,
+                    format!(
+                        "{} has {:.1}% of pixels darker than {} sRGB, the darkest albedo of real materials",
+                        map,
+                        dark * 100.0,
+                        darkest
+                    ),
+                );
+            }
+
+            if let Some(severity) = severity(bright, 0.01) {
+                push(
+                    severity,
+                    format!(
+                        "{} has {:.1}% of pixels brighter than {} sRGB, the brightest albedo of real materials",
+                        map,
+                        bright * 100.0,
+                        brightest
+                    ),
+                );
+            }
+        }
+        Map::Metallic => {
+            let (low, high) = PARTLY_METALLIC_RANGE;
+            let count = (pixels.width() * pixels.height()).max(1) as f32;
+
+            let partly = pixels
+                .pixels()
+                .filter(|pixel| (low..=high).contains(&pixel[0]))
+                .count() as f32
+                / count;
+
+            // Edges between metal and other materials are blended, so more
+            // partly metallic pixels are allowed
+            if let Some(severity) = severity(partly, 0.05) {
+                push(
+                    severity,
+                    format!(
+                        "{} has {:.1}% of pixels between {} and {}, where surfaces should be either metal or not",
+                        map,
+                        partly * 100.0,
+                        low,
+                        high
+                    ),
+                );
+            }
+        }
+        Map::Roughness if pixels.pixels().all(|pixel| pixel[0] < 0.5 / 255.0) => push(
+            Severity::Error,
+            format!(
+                "{} is 0 everywhere, which makes a perfect mirror, so smoothness or roughness is probably missing",
+                map
+            ),
+        ),
+        _ => {}
+    }
+}
+
+/// Checks a texture for values which are not physically plausible
+///
+/// Base colour and diffuse maps should stay within [`ALBEDO_RANGE`],
+/// metallic maps should be either metal or not, and roughness should not
+/// be 0 everywhere. Packed textures are split with `curve` and each of
+/// their maps checked, and must have an alpha channel if their layout
+/// stores a map in it. Images are in the usual colour space of what they
+/// hold.
+pub fn check(texture: Texture, image: DynamicImage, curve: RoughnessCurve) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    match texture {
+        Texture::Map(map) => check_map(map, &image, None, &mut issues),
+        Texture::Packed(layout) => {
+            if layout.needs_alpha() && !image.color().has_alpha() {
+                issues.push(Issue {
+                    severity: Severity::Error,
+                    message: format!(
+                        "{} texture has no alpha channel, so it has no smoothness",
+                        layout.description
+                    ),
+                });
+
+                return issues;
+            }
+
+            let from = format!("{} texture's", layout.description);
+
+            match layout.split(image, curve) {
+                Ok(maps) => {
+                    for (map, image) in maps {
+                        check_map(map, &image, Some(&from), &mut issues);
+                    }
+                }
+                Err(error) => issues.push(Issue {
+                    severity: Severity::Error,
+                    message: format!(
+                        "{} texture cannot be split: {:#}",
+                        layout.description, error
+                    ),
+                }),
+            }
+        }
+    }
+
+    issues
+}
@@ -1,89 +1,860 @@
 #[macro_use]
 extern crate log;
 
+#[macro_use]
+mod report;
+
+mod batch;
+mod color_options;
+mod naming;
+mod output;
+
 use anyhow::{bail, Result};
-use image::{GenericImage, GenericImageView, ImageBuffer, Pixel};
-use std::path::PathBuf;
+use color_options::ColorSpaceOptions;
+use matknife::color::{self, ColorSpace};
+use matknife::curve::{self, RoughnessCurve};
+use matknife::layout::{self, Layout, Map, MergeOptions, SplitOptions};
+use matknife::lint::{self, Severity};
+use matknife::mipmap::Toksvig;
+use matknife::normal::{self, NormalConvention, NormalOptions};
+use matknife::pack::{self, Channel, ChannelSource};
+use matknife::texture_set::{self, Convention, InputSuffix, Texture, TextureSet};
+use matknife::{image_file, resize, workflow};
+use naming::{NamingOptions, OutputName};
+use output::OutputOptions;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use structopt::StructOpt;
 
+/// The directory a file is in, for writing output files next to it
+fn directory_of(file: &Path) -> &Path {
+    file.parent().unwrap_or_else(|| Path::new(""))
+}
+
 #[derive(Debug, StructOpt)]
 /// Split a Unity-style combined metallic and smoothness texture image
-/// into Pixar USD-style separate images for metallic and roughness.
+/// into Pixar USD-style separate images for metallic and roughness, and
+/// for occlusion and detail mask when the layout includes them.
+///
+/// Any number of texture files can be split at once, across every CPU
+/// core, and a summary of which succeeded is printed at the end.
 struct Split {
-    /// The texture file to split
+    /// The texture files to split
     ///
-    /// Must be a greyscale image with an alpha channel, where black means
-    /// non-metallic and white means metallic, and completely transparent
-    /// means perfectly rough and completely opaque means perfectly smooth
-    #[structopt(parse(from_os_str))]
-    file: PathBuf,
+    /// For the `unity` layout, must be a greyscale image with an alpha
+    /// channel, where black means non-metallic and white means metallic,
+    /// and completely transparent means perfectly rough and completely
+    /// opaque means perfectly smooth. Without an alpha channel, smoothness
+    /// must come from `--default-smoothness`, `--smoothness-channel` or
+    /// `--smoothness-file`.
+    ///
+    /// Directories and glob patterns such as `textures/**/*.png` pick up
+    /// every file in them named like a packed texture of the layout
+    #[structopt(parse(from_os_str), required = true)]
+    files: Vec<PathBuf>,
+
+    /// Look for texture files in subdirectories of directories too
+    #[structopt(short, long)]
+    recursive: bool,
+
+    /// How the texture file is packed
+    ///
+    /// See `matknife --help` for what each layout holds
+    #[structopt(
+        long,
+        default_value = "unity",
+        possible_values = layout::NAMES,
+        parse(try_from_str = layout::find)
+    )]
+    layout: &'static Layout,
+
+    /// How smoothness in the packed texture relates to roughness
+    ///
+    /// One of `linear` for smoothness of one minus roughness, `squared` (or
+    /// `perceptual`) for roughness maps holding squared "alpha" roughness,
+    /// or `phong` (or `beckmann`) for smoothness holding a Blinn-Phong
+    /// specular exponent
+    #[structopt(
+        long,
+        default_value = "linear",
+        possible_values = curve::NAMES
+    )]
+    roughness_curve: RoughnessCurve,
+
+    /// The smoothness, from 0 to 1, to use everywhere in texture files
+    /// which have no alpha channel
+    ///
+    /// A warning is given for each file it is used for
+    #[structopt(long, conflicts_with_all = &["smoothness-channel", "smoothness-file"])]
+    default_smoothness: Option<f32>,
+
+    /// The channel to read smoothness from, rather than alpha
+    ///
+    /// One of r, g, b, a, l (luminance), avg or max, of the smoothness file
+    /// if one is given, or of the texture file otherwise
+    #[structopt(long)]
+    smoothness_channel: Option<Channel>,
+
+    /// A greyscale file to read smoothness from, rather than the alpha
+    /// channel of the texture file
+    ///
+    /// Must be the same size as the texture files
+    #[structopt(long, parse(from_os_str))]
+    smoothness_file: Option<PathBuf>,
+
+    /// The channel to read metallic from, rather than the one the layout
+    /// stores it in
+    ///
+    /// One of r, g, b, a, l (luminance), avg (the average of r, g and b)
+    /// or max (the brightest of r, g and b). Without one, texture files
+    /// whose red, green and blue channels all hold metallic but differ are
+    /// read from red, with a warning
+    #[structopt(long)]
+    metallic_channel: Option<Channel>,
+
+    #[structopt(flatten)]
+    naming: NamingOptions,
+
+    #[structopt(flatten)]
+    color: ColorSpaceOptions,
+
+    #[structopt(flatten)]
+    output_options: OutputOptions,
+}
+
+fn split_file(file: &Path, options: &Split, split_options: &SplitOptions) -> Result<()> {
+    progress!(
+        "Splitting {:?} into {} files...",
+        file,
+        options.layout.unpacked.len()
+    );
+
+    let image = options.color.open(file, ColorSpace::Linear)?;
+
+    if let Some(smoothness_file) = &options.smoothness_file {
+        report::input(smoothness_file);
+    }
+
+    let filename = options.naming.stem(
+        file,
+        Texture::Packed(options.layout),
+        options.layout.prefix,
+        options.layout.suffix,
+    )?;
+
+    debug!("filename: {:?}", filename);
+
+    let maps = options
+        .layout
+        .split_with(image, options.roughness_curve, split_options)?;
+
+    for (map, map_image) in maps {
+        let map_path = options.naming.path(
+            directory_of(file),
+            OutputName {
+                prefix: "",
+                stem: &filename,
+                suffix: map.suffix(),
+            },
+        );
+
+        progress!("Writing {} texture to: {:?}", map, map_path);
+        options.color.save(
+            map_image,
+            &map_path,
+            Texture::Map(map),
+            None,
+            &options.output_options,
+        )?;
+    }
+
+    Ok(())
 }
 
-fn split(options: Split) -> Result<()> {
+fn split(mut options: Split) -> Result<()> {
     debug!("{:?}", options);
 
-    println!("Splitting {:?} into two files...", options.file);
+    let split_options = SplitOptions {
+        default_smoothness: options.default_smoothness,
+        smoothness_channel: options.smoothness_channel,
+        smoothness_image: options
+            .smoothness_file
+            .as_ref()
+            .map(|file| color::open(file, ColorSpace::Linear, options.color.input_colorspace))
+            .transpose()?,
+        metallic_channel: options.metallic_channel,
+    };
+
+    split_options.check(options.layout)?;
+
+    let files = batch::expand(&options.files, options.recursive, |file| {
+        image_file::is_image(file)
+            && matches!(
+                texture_set::recognise(file, &options.naming.input_suffixes),
+                Some((_, Texture::Packed(layout), _)) if layout.name == options.layout.name
+            )
+    })?;
+
+    options.naming.set_inputs(&files);
+
+    batch::summarise(batch::run(
+        &files,
+        |file| file.display().to_string(),
+        |file| split_file(file, &options, &split_options),
+    ))
+}
+
+#[derive(Debug, StructOpt)]
+/// Merge Pixar USD-style separate images for metallic and roughness, and
+/// optionally occlusion and detail mask, into a Unity-style combined
+/// metallic and smoothness texture image.
+///
+/// Texture files are grouped into sets by name, such as `RustyMetallic.png`
+/// and `RustyRoughness.png`, and each set is merged into its own file.
+/// Any number of sets can be merged at once, across every CPU core, and a
+/// summary of which succeeded is printed at the end.
+struct Merge {
+    /// The texture files to merge
+    ///
+    /// Metallic files must be greyscale images where black means
+    /// non-metallic, and white means metallic. Roughness files must be
+    /// greyscale images where white means perfectly rough, and black means
+    /// perfectly smooth. Colour files are read from one of their channels,
+    /// chosen with `--metallic-channel` and `--roughness-channel`.
+    ///
+    /// Directories and glob patterns such as `textures/**/*.png` pick up
+    /// every file in them named like a map of the layout. Two files with
+    /// other names are merged as a metallic file and a roughness file, in
+    /// that order
+    #[structopt(parse(from_os_str), required = true)]
+    files: Vec<PathBuf>,
+
+    /// Look for texture files in subdirectories of directories too
+    #[structopt(short, long)]
+    recursive: bool,
+
+    /// The ambient occlusion file, for layouts which include occlusion,
+    /// when merging a single set
+    ///
+    /// Must be a greyscale image where black means fully occluded. Without
+    /// one, the surface is treated as not occluded at all
+    #[structopt(long, parse(from_os_str))]
+    occlusion_file: Option<PathBuf>,
+
+    /// The detail mask file, for layouts which include a detail mask, when
+    /// merging a single set
+    ///
+    /// Must be a greyscale image where white means detail textures are
+    /// fully applied. Without one, detail textures apply everywhere
+    #[structopt(long, parse(from_os_str))]
+    detail_mask_file: Option<PathBuf>,
+
+    /// The normal map file, for widening roughness in mip levels, when
+    /// merging a single set
+    ///
+    /// Without one, a normal map named like the other texture files of a
+    /// set is used if there is one. Only used with `--mipmaps`, or when the
+    /// merged texture file is shrunk
+    #[structopt(long, parse(from_os_str))]
+    normal_file: Option<PathBuf>,
+
+    /// The channel of metallic files to read metallic from
+    ///
+    /// One of r, g, b, a, l (luminance), avg (the average of r, g and b)
+    /// or max (the brightest of r, g and b). Without one, metallic files
+    /// which are not greyscale are read from red, with a warning
+    #[structopt(long)]
+    metallic_channel: Option<Channel>,
+
+    /// The channel of roughness files to read roughness from
+    ///
+    /// One of r, g, b, a, l (luminance), avg or max. Without one, roughness
+    /// files which are not greyscale, such as tinted ones, are read from
+    /// red, with a warning
+    #[structopt(long)]
+    roughness_channel: Option<Channel>,
+
+    /// The size to resize files to when they are not all the same size
+    ///
+    /// One of `larger` or `smaller` for the size of the file with the most
+    /// or fewest pixels, a map such as `metallic` or `roughness` for the
+    /// size of that map's file, or a size such as `1024x1024`. A size
+    /// resizes every file, even if they already match. Without one, files
+    /// of different sizes are not merged
+    #[structopt(long)]
+    resize_to: Option<resize::Target>,
 
-    let mut image = image::open(options.file.clone())?;
+    /// How to blend pixels when resizing files with `--resize-to`
+    ///
+    /// One of `nearest`, `bilinear`, `lanczos` or `box`. `box` averages
+    /// every pixel covered, which suits shrinking files
+    #[structopt(
+        long,
+        default_value = "bilinear",
+        possible_values = resize::NAMES
+    )]
+    resize_filter: resize::Filter,
+
+    /// How to pack the merged texture file
+    ///
+    /// See `matknife --help` for what each layout holds
+    #[structopt(
+        long,
+        default_value = "unity",
+        possible_values = layout::NAMES,
+        parse(try_from_str = layout::find)
+    )]
+    layout: &'static Layout,
+
+    /// How smoothness in the packed texture relates to roughness
+    ///
+    /// One of `linear` for smoothness of one minus roughness, `squared` (or
+    /// `perceptual`) for roughness maps holding squared "alpha" roughness,
+    /// or `phong` (or `beckmann`) for smoothness holding a Blinn-Phong
+    /// specular exponent
+    #[structopt(
+        long,
+        default_value = "linear",
+        possible_values = curve::NAMES
+    )]
+    roughness_curve: RoughnessCurve,
 
-    if !image.color().has_alpha() {
-        bail!("Input image does not have an alpha channel!");
+    #[structopt(flatten)]
+    naming: NamingOptions,
+
+    #[structopt(flatten)]
+    color: ColorSpaceOptions,
+
+    #[structopt(flatten)]
+    output_options: OutputOptions,
+}
+
+/// The separate texture files making up one merged texture file
+#[derive(Debug)]
+struct MergeSet {
+    /// Where to write the merged texture file
+    path: PathBuf,
+    files: Vec<(Map, PathBuf)>,
+    /// The normal map of the set, for widening roughness in mip levels
+    normal: Option<PathBuf>,
+}
+
+fn merge_set(set: &MergeSet, options: &Merge) -> Result<()> {
+    for required in [Map::Metallic, Map::Roughness] {
+        if !set.files.iter().any(|(map, _)| *map == required) {
+            bail!("There is no {} file to merge!", required);
+        }
     }
 
-    let file_stem = options
-        .file
-        .file_stem()
-        .expect("Could not determine file name");
+    let images = options
+        .layout
+        .maps
+        .iter()
+        .map(|map| {
+            let file = set
+                .files
+                .iter()
+                .find(|(file_map, _)| file_map == map)
+                .map(|(_, file)| file);
+
+            file.map(|file| options.color.open(file, map.color_space()))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let merge_options = MergeOptions {
+        metallic_channel: options.metallic_channel,
+        roughness_channel: options.roughness_channel,
+        resize_to: options.resize_to,
+        resize_filter: options.resize_filter,
+    };
 
-    let (width, height) = image.dimensions();
-    let mut alpha_image: ImageBuffer<image::Luma<u8>, Vec<_>> = ImageBuffer::new(width, height);
+    progress!(
+        "Merging {:?} into one file...",
+        set.files.iter().map(|(_, file)| file).collect::<Vec<_>>()
+    );
+
+    let merged_image =
+        options
+            .layout
+            .merge_with(images, options.roughness_curve, &merge_options)?;
+
+    let normal = match &set.normal {
+        Some(file) if options.output_options.toksvig() => {
+            debug!("Widening roughness in mip levels with {:?}", file);
+
+            Some(options.color.open(file, Map::Normal.color_space())?)
+        }
+        _ => None,
+    };
 
-    for y_position in 0..height {
-        for x_position in 0..width {
-            let mut output_pixel = image::Luma::<u8>([0x00]);
+    let toksvig = normal.as_ref().map(|normal| Toksvig {
+        normal,
+        curve: options.roughness_curve,
+    });
 
-            let input_pixel = image.get_pixel(x_position, y_position).map_with_alpha(
-                |channel| channel,
-                |alpha| {
-                    output_pixel = image::Luma::<u8>([0xff - alpha]);
-                    0xff
+    progress!(
+        "Writing {} file to: {:?}",
+        options.layout.description,
+        set.path
+    );
+
+    options.color.save(
+        merged_image,
+        &set.path,
+        Texture::Packed(options.layout),
+        toksvig.as_ref(),
+        &options.output_options,
+    )?;
+
+    Ok(())
+}
+
+fn merge(mut options: Merge) -> Result<()> {
+    debug!("{:?}", options);
+
+    let files = batch::expand(&options.files, options.recursive, |file| {
+        image_file::is_image(file)
+            && matches!(
+                texture_set::recognise(file, &options.naming.input_suffixes),
+                Some((_, Texture::Map(map), _)) if options.layout.maps.contains(&map)
+                    || (map == Map::Normal && options.output_options.toksvig())
+            )
+    })?;
+
+    options.naming.set_inputs(&files);
+
+    let mut sets: Vec<MergeSet> = texture_set::group(&files, &options.naming.input_suffixes)
+        .into_iter()
+        .map(|set| MergeSet {
+            normal: set
+                .files
+                .iter()
+                .find(|file| file.texture == Texture::Map(Map::Normal))
+                .map(|file| file.path.clone()),
+            path: options.naming.path(
+                &set.directory,
+                OutputName {
+                    prefix: options.layout.prefix,
+                    stem: &set.stem,
+                    suffix: options.layout.suffix,
                 },
-            );
+            ),
+            files: set
+                .files
+                .into_iter()
+                .filter_map(|file| match file.texture {
+                    Texture::Map(map) if options.layout.maps.contains(&map) => {
+                        Some((map, file.path))
+                    }
+                    _ => None,
+                })
+                .collect(),
+        })
+        .filter(|set| !set.files.is_empty())
+        .collect();
+
+    // Two files which are not a set on their own are a metallic file and a
+    // roughness file, however they are named
+    if files.len() == 2 && !sets.iter().any(|set| set.files.len() == 2) {
+        let filename = options.naming.stem(
+            &files[0],
+            Texture::Map(Map::Metallic),
+            "",
+            Map::Metallic.suffix(),
+        )?;
+
+        debug!("filename: {:?}", filename);
+
+        sets = vec![MergeSet {
+            path: options.naming.path(
+                directory_of(&files[0]),
+                OutputName {
+                    prefix: options.layout.prefix,
+                    stem: &filename,
+                    suffix: options.layout.suffix,
+                },
+            ),
+            files: vec![
+                (Map::Metallic, files[0].clone()),
+                (Map::Roughness, files[1].clone()),
+            ],
+            normal: None,
+        }];
+    }
+
+    if let Some(file) = &options.normal_file {
+        if sets.len() != 1 {
+            bail!("A normal file can only be given when merging a single set!");
+        }
+
+        sets[0].normal = Some(file.clone());
+    }
+
+    let extra_files = [
+        (Map::Occlusion, options.occlusion_file.as_ref()),
+        (Map::DetailMask, options.detail_mask_file.as_ref()),
+    ];
+
+    for (map, file) in extra_files {
+        if let Some(file) = file {
+            if !options.layout.maps.contains(&map) {
+                warn!(
+                    "The {} layout has no {} map, ignoring {:?}",
+                    options.layout.name, map, file
+                );
+                continue;
+            }
 
-            image.put_pixel(x_position, y_position, input_pixel);
-            alpha_image.put_pixel(x_position, y_position, output_pixel);
+            if sets.len() != 1 {
+                bail!(
+                    "A {} file can only be given when merging a single set!",
+                    map
+                );
+            }
+
+            sets[0].files.retain(|(file_map, _)| *file_map != map);
+            sets[0].files.push((map, file.clone()));
         }
     }
 
-    let mut filename: String = file_stem.to_string_lossy().to_string();
+    debug!("sets: {:?}", sets);
+
+    batch::summarise(batch::run(
+        &sets,
+        |set| set.path.display().to_string(),
+        |set| merge_set(set, &options),
+    ))
+}
+
+#[derive(Debug, StructOpt)]
+/// Pack channels from any number of images into a new image.
+///
+/// Each output channel is given as `<input>:<channel>`, where `<input>` is
+/// the position of the input file starting from 0 and `<channel>` is one of
+/// r, g, b, a, l (luma), avg (the average of r, g and b) or max (the
+/// brightest of r, g and b), optionally followed by `:invert`. A number from
+/// 0 to 255 fills the channel with that value instead.
+struct Pack {
+    /// The texture files to read channels from
+    #[structopt(parse(from_os_str), required = true)]
+    files: Vec<PathBuf>,
+
+    /// Where to write the packed texture
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// Source for the red channel
+    #[structopt(short, long, conflicts_with = "luma")]
+    red: Option<ChannelSource>,
+
+    /// Source for the green channel
+    #[structopt(short, long, conflicts_with = "luma")]
+    green: Option<ChannelSource>,
+
+    /// Source for the blue channel
+    #[structopt(short, long, conflicts_with = "luma")]
+    blue: Option<ChannelSource>,
+
+    /// Source for the alpha channel
+    ///
+    /// Without one, the packed texture will not have an alpha channel
+    #[structopt(short, long)]
+    alpha: Option<ChannelSource>,
+
+    /// Source for a single greyscale channel, instead of red, green and blue
+    #[structopt(short, long)]
+    luma: Option<ChannelSource>,
+
+    /// Value for red, green or blue channels which were not given a source
+    #[structopt(long, default_value = "0")]
+    fill: u8,
+
+    #[structopt(flatten)]
+    output_options: OutputOptions,
+}
+
+fn pack(options: Pack) -> Result<()> {
+    debug!("{:?}", options);
+
+    let mut sources = match options.luma {
+        Some(luma) => vec![luma],
+        None => [options.red, options.green, options.blue]
+            .iter()
+            .map(|source| source.unwrap_or(ChannelSource::Constant(options.fill)))
+            .collect(),
+    };
 
-    if let Some(basename) = filename.strip_suffix("MetallicSmoothness") {
-        filename = basename.to_string();
+    if let Some(alpha) = options.alpha {
+        sources.push(alpha);
     }
 
+    debug!("sources: {:?}", sources);
+
+    progress!("Packing {:?} into one file...", options.files);
+
+    let inputs = options
+        .files
+        .iter()
+        .map(|file| {
+            report::input(file);
+            image_file::open(file)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let packed_image = pack::pack(&inputs, &sources)?;
+
+    progress!("Writing packed texture to: {:?}", options.output);
+
+    options
+        .output_options
+        .save(&packed_image, &options.output, None, None, None)?;
+
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+/// Convert a packed texture image from one layout to another, such as
+/// from a Unity-style combined metallic and smoothness texture image to an
+/// Unreal-style occlusion, roughness and metallic texture image.
+///
+/// Maps which the new layout does not have are dropped, and maps which the
+/// original layout does not have are filled with their default value.
+struct Convert {
+    /// The texture file to convert
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+
+    /// How the texture file is packed
+    ///
+    /// See `matknife --help` for what each layout holds
+    #[structopt(
+        long,
+        possible_values = layout::NAMES,
+        parse(try_from_str = layout::find)
+    )]
+    from: &'static Layout,
+
+    /// How to pack the converted texture file
+    ///
+    /// See `matknife --help` for what each layout holds
+    #[structopt(
+        long,
+        possible_values = layout::NAMES,
+        parse(try_from_str = layout::find)
+    )]
+    to: &'static Layout,
+
+    /// How smoothness in the packed texture relates to roughness
+    ///
+    /// One of `linear` for smoothness of one minus roughness, `squared` (or
+    /// `perceptual`) for roughness maps holding squared "alpha" roughness,
+    /// or `phong` (or `beckmann`) for smoothness holding a Blinn-Phong
+    /// specular exponent
+    #[structopt(
+        long,
+        default_value = "linear",
+        possible_values = curve::NAMES
+    )]
+    roughness_curve: RoughnessCurve,
+
+    #[structopt(flatten)]
+    naming: NamingOptions,
+
+    #[structopt(flatten)]
+    color: ColorSpaceOptions,
+
+    #[structopt(flatten)]
+    output_options: OutputOptions,
+}
+
+fn convert(options: Convert) -> Result<()> {
+    debug!("{:?}", options);
+
+    progress!(
+        "Converting {:?} from {} to {}...",
+        options.file,
+        options.from.name,
+        options.to.name
+    );
+
+    let image = options.color.open(&options.file, ColorSpace::Linear)?;
+
+    let mut maps = options.from.split(image, options.roughness_curve)?;
+
+    for (map, _) in &maps {
+        if !options.to.maps.contains(map) {
+            warn!(
+                "The {} layout has no {} map, dropping it",
+                options.to.name, map
+            );
+        }
+    }
+
+    let images = options
+        .to
+        .maps
+        .iter()
+        .map(|map| {
+            maps.iter()
+                .position(|(split_map, _)| split_map == map)
+                .map(|index| maps.swap_remove(index).1)
+        })
+        .collect();
+
+    let converted_image = options.to.merge(images, options.roughness_curve)?;
+
+    let filename = options.naming.stem(
+        &options.file,
+        Texture::Packed(options.from),
+        options.from.prefix,
+        options.from.suffix,
+    )?;
+
     debug!("filename: {:?}", filename);
 
-    let metallic_path = options
-        .file
-        .with_file_name(format!("{}{}", filename, "Metallic.png"));
+    let converted_path = options.naming.path(
+        directory_of(&options.file),
+        OutputName {
+            prefix: options.to.prefix,
+            stem: &filename,
+            suffix: options.to.suffix,
+        },
+    );
 
-    println!("Writing metallic texture to: {:?}", metallic_path);
-    image.save(metallic_path)?;
+    naming::check_not_input(&converted_path, std::slice::from_ref(&options.file))?;
 
-    let roughness_path = options
-        .file
-        .with_file_name(format!("{}{}", filename, "Roughness.png"));
+    progress!(
+        "Writing {} file to: {:?}",
+        options.to.description,
+        converted_path
+    );
 
-    println!("Writing roughness texture to: {:?}", roughness_path);
-    alpha_image.save(roughness_path)?;
+    options.color.save(
+        converted_image,
+        &converted_path,
+        Texture::Packed(options.to),
+        None,
+        &options.output_options,
+    )?;
 
     Ok(())
 }
 
 #[derive(Debug, StructOpt)]
-/// Merge Pixar USD-style separate images for metallic and roughness
-/// into a Unity-style combined metallic and smoothness texture image.
-struct Merge {
+/// Convert specular and glossiness texture images, as used by Unity's
+/// Standard (Specular setup) shader and glTF's
+/// KHR_materials_pbrSpecularGlossiness extension, into base colour,
+/// metallic and roughness texture images.
+struct Spec2Metal {
+    /// The diffuse file
+    ///
+    /// Must be a colour image, optionally with opacity in the alpha channel
+    #[structopt(parse(from_os_str))]
+    diffuse_file: PathBuf,
+
+    /// The specular file
+    ///
+    /// Must be a colour image, with glossiness in the alpha channel unless
+    /// a separate glossiness file is given
+    #[structopt(parse(from_os_str))]
+    specular_file: PathBuf,
+
+    /// The glossiness file, if glossiness is not in the specular file's
+    /// alpha channel
+    ///
+    /// Must be a greyscale image where white means perfectly smooth, and
+    /// black means perfectly rough
+    #[structopt(long, parse(from_os_str))]
+    glossiness_file: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    naming: NamingOptions,
+
+    #[structopt(flatten)]
+    color: ColorSpaceOptions,
+
+    #[structopt(flatten)]
+    output_options: OutputOptions,
+}
+
+fn spec2metal(options: Spec2Metal) -> Result<()> {
+    debug!("{:?}", options);
+
+    let textures = workflow::SpecularGlossiness {
+        diffuse: options
+            .color
+            .open(&options.diffuse_file, Map::Diffuse.color_space())?,
+        specular_glossiness: options.color.open(
+            &options.specular_file,
+            Map::SpecularGlossiness.color_space(),
+        )?,
+    };
+
+    let glossiness_image = options
+        .glossiness_file
+        .as_ref()
+        .map(|file| options.color.open(file, ColorSpace::Linear))
+        .transpose()?;
+
+    progress!(
+        "Converting {:?} and {:?} to metallic and roughness...",
+        options.diffuse_file,
+        options.specular_file
+    );
+
+    let converted = workflow::specular_to_metallic(&textures, glossiness_image.as_ref())?;
+
+    let filename = options.naming.stem(
+        &options.diffuse_file,
+        Texture::Map(Map::Diffuse),
+        "",
+        Map::Diffuse.suffix(),
+    )?;
+
+    debug!("filename: {:?}", filename);
+
+    for (map, map_image) in [
+        (Map::BaseColor, converted.base_color),
+        (Map::Metallic, converted.metallic),
+        (Map::Roughness, converted.roughness),
+    ] {
+        let map_path = options.naming.path(
+            directory_of(&options.diffuse_file),
+            OutputName {
+                prefix: "",
+                stem: &filename,
+                suffix: map.suffix(),
+            },
+        );
+
+        progress!("Writing {} texture to: {:?}", map, map_path);
+        options.color.save(
+            map_image,
+            &map_path,
+            Texture::Map(map),
+            None,
+            &options.output_options,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+/// Convert base colour, metallic and roughness texture images into
+/// specular and glossiness texture images, as used by Unity's Standard
+/// (Specular setup) shader and glTF's KHR_materials_pbrSpecularGlossiness
+/// extension.
+///
+/// Glossiness is written to the alpha channel of the specular texture.
+struct Metal2Spec {
+    /// The base colour file
+    ///
+    /// Must be a colour image, optionally with opacity in the alpha channel
+    #[structopt(parse(from_os_str))]
+    base_color_file: PathBuf,
+
     /// The metallic file
     ///
     /// Must be a greyscale image where black means non-metallic,
@@ -97,86 +868,561 @@ struct Merge {
     /// and black means perfectly smooth
     #[structopt(parse(from_os_str))]
     roughness_file: PathBuf,
+
+    #[structopt(flatten)]
+    naming: NamingOptions,
+
+    #[structopt(flatten)]
+    color: ColorSpaceOptions,
+
+    #[structopt(flatten)]
+    output_options: OutputOptions,
 }
 
-fn merge(options: Merge) -> Result<()> {
+fn metal2spec(options: Metal2Spec) -> Result<()> {
     debug!("{:?}", options);
 
-    let mut metallic_image = image::open(options.metallic_file.clone())?;
-    let roughness_image = image::open(options.roughness_file.clone())?;
+    let textures = workflow::MetallicRoughness {
+        base_color: options
+            .color
+            .open(&options.base_color_file, Map::BaseColor.color_space())?,
+        metallic: options
+            .color
+            .open(&options.metallic_file, Map::Metallic.color_space())?,
+        roughness: options
+            .color
+            .open(&options.roughness_file, Map::Roughness.color_space())?,
+    };
 
-    println!(
-        "Merging {:?} and {:?} into one file...",
-        options.metallic_file, options.roughness_file
+    progress!(
+        "Converting {:?}, {:?} and {:?} to specular and glossiness...",
+        options.base_color_file,
+        options.metallic_file,
+        options.roughness_file
     );
 
-    if metallic_image.dimensions() != roughness_image.dimensions() {
-        bail!("Input images are not the same size!");
+    let converted = workflow::metallic_to_specular(&textures)?;
+
+    let filename = options.naming.stem(
+        &options.base_color_file,
+        Texture::Map(Map::BaseColor),
+        "",
+        Map::BaseColor.suffix(),
+    )?;
+
+    debug!("filename: {:?}", filename);
+
+    for (map, map_image) in [
+        (Map::Diffuse, converted.diffuse),
+        (Map::SpecularGlossiness, converted.specular_glossiness),
+    ] {
+        let map_path = options.naming.path(
+            directory_of(&options.base_color_file),
+            OutputName {
+                prefix: "",
+                stem: &filename,
+                suffix: map.suffix(),
+            },
+        );
+
+        progress!("Writing {} texture to: {:?}", map, map_path);
+        options.color.save(
+            map_image,
+            &map_path,
+            Texture::Map(map),
+            None,
+            &options.output_options,
+        )?;
     }
 
-    let (width, height) = metallic_image.dimensions();
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+/// Convert a normal map texture image between the OpenGL (Y+) and DirectX
+/// (Y-) conventions, optionally fixing up its vectors.
+///
+/// Unity, Blender and USD use OpenGL-style normal maps, while Unreal Engine
+/// uses DirectX-style normal maps.
+struct Normal {
+    /// The normal map file
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+
+    /// The convention the normal map file uses, either `opengl` or `directx`
+    #[structopt(long, default_value = "opengl", possible_values = normal::NAMES)]
+    from: NormalConvention,
+
+    /// The convention to convert the normal map to, either `opengl` or
+    /// `directx`
+    #[structopt(long, default_value = "directx", possible_values = normal::NAMES)]
+    to: NormalConvention,
+
+    /// Scale every vector back to unit length
+    #[structopt(long)]
+    renormalize: bool,
 
-    for y_position in 0..height {
-        for x_position in 0..width {
-            let mut value: u8 = 0x00;
+    /// Ignore the blue channel and work it out from red and green, for
+    /// two-channel (BC5 or RG) normal maps
+    #[structopt(long)]
+    reconstruct_z: bool,
 
-            roughness_image
-                .get_pixel(x_position, y_position)
-                .map(|channel| {
-                    value = channel;
-                    channel
-                });
+    /// Write black to the blue channel, to make a two-channel (BC5 or RG)
+    /// normal map
+    #[structopt(long)]
+    drop_z: bool,
 
-            let new_pixel = metallic_image
-                .get_pixel(x_position, y_position)
-                .map_with_alpha(|_channel| 0x00, |_alpha| 0xff - value);
+    /// Where to write the converted normal map
+    ///
+    /// Defaults to the normal map file's name, with a suffix saying which
+    /// convention it uses
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    naming: NamingOptions,
+
+    #[structopt(flatten)]
+    color: ColorSpaceOptions,
+
+    #[structopt(flatten)]
+    output_options: OutputOptions,
+}
+
+fn normal(options: Normal) -> Result<()> {
+    debug!("{:?}", options);
+
+    progress!(
+        "Converting {:?} from {} to {}...",
+        options.file,
+        options.from,
+        options.to
+    );
+
+    let converted_path = match options.output {
+        Some(output) => output,
+        None => {
+            let filename = options.naming.stem(
+                &options.file,
+                Texture::Map(Map::Normal),
+                "",
+                options.from.suffix(),
+            )?;
+            let filename = filename
+                .strip_suffix(Map::Normal.suffix())
+                .unwrap_or(&filename);
 
-            metallic_image.put_pixel(x_position, y_position, new_pixel);
+            debug!("filename: {:?}", filename);
+
+            options.naming.path(
+                directory_of(&options.file),
+                OutputName {
+                    prefix: "",
+                    stem: filename,
+                    suffix: &format!("{}{}", Map::Normal.suffix(), options.to.suffix()),
+                },
+            )
         }
+    };
+
+    if converted_path == options.file {
+        bail!("Converting would overwrite the normal map file, give an --output instead!");
     }
 
-    let file_stem = options
-        .metallic_file
-        .file_stem()
-        .expect("Could not determine file name");
+    let image = options.color.open(&options.file, ColorSpace::Linear)?;
+
+    let converted_image = normal::convert(
+        &image,
+        options.from,
+        options.to,
+        NormalOptions {
+            renormalize: options.renormalize,
+            reconstruct_z: options.reconstruct_z,
+            drop_z: options.drop_z,
+        },
+    );
 
-    let mut filename: String = file_stem.to_string_lossy().to_string();
+    progress!("Writing normal texture to: {:?}", converted_path);
+
+    options.color.save(
+        converted_image,
+        &converted_path,
+        Texture::Map(Map::Normal),
+        None,
+        &options.output_options,
+    )?;
+
+    Ok(())
+}
 
-    if let Some(basename) = filename.strip_suffix("Metallic") {
-        filename = basename.to_string();
+#[derive(Debug, StructOpt)]
+/// Convert every texture set in a directory from one engine's conventions
+/// to another's.
+///
+/// Texture sets are converted across every CPU core, and a summary of which
+/// succeeded is printed at the end.
+///
+/// Texture files are grouped into sets by name, and recognised by suffixes
+/// such as BaseColor or Albedo, Metallic, Roughness, MetallicSmoothness,
+/// Normal, AO, Height and Emission. Packed textures are split and merged
+/// into the new layout, normal maps are flipped if the conventions differ,
+/// and every file is renamed to match.
+///
+/// Conventions are `usd` for separate files, `unity` and `hdrp` for Unity
+/// and its High Definition Render Pipeline, `gltf` for glTF and `unreal`
+/// for Unreal Engine.
+struct ConvertSet {
+    /// The directories holding the texture files
+    ///
+    /// Texture files and glob patterns such as `textures/**/*.png` can be
+    /// given too
+    #[structopt(parse(from_os_str), required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Look for texture files in subdirectories of directories too
+    #[structopt(short, long)]
+    recursive: bool,
+
+    /// The conventions the texture files use
+    #[structopt(
+        long,
+        possible_values = texture_set::NAMES,
+        parse(try_from_str = texture_set::find)
+    )]
+    from: &'static Convention,
+
+    /// The conventions to convert the texture files to
+    #[structopt(
+        long,
+        possible_values = texture_set::NAMES,
+        parse(try_from_str = texture_set::find)
+    )]
+    to: &'static Convention,
+
+    /// How smoothness in packed textures relates to roughness
+    ///
+    /// One of `linear` for smoothness of one minus roughness, `squared` (or
+    /// `perceptual`) for roughness maps holding squared "alpha" roughness,
+    /// or `phong` (or `beckmann`) for smoothness holding a Blinn-Phong
+    /// specular exponent
+    #[structopt(
+        long,
+        default_value = "linear",
+        possible_values = curve::NAMES
+    )]
+    roughness_curve: RoughnessCurve,
+
+    #[structopt(flatten)]
+    naming: NamingOptions,
+
+    #[structopt(flatten)]
+    color: ColorSpaceOptions,
+
+    #[structopt(flatten)]
+    output_options: OutputOptions,
+}
+
+/// Converts a texture set, refusing to write over any of `inputs`, the
+/// input files of the whole batch
+fn convert_texture_set(set: &TextureSet, inputs: &[PathBuf], options: &ConvertSet) -> Result<()> {
+    debug!("set: {:?}", set);
+
+    progress!("Converting {:?}...", set.directory.join(&set.name));
+
+    for file in &set.files {
+        report::input(&file.path);
     }
 
-    debug!("filename: {:?}", filename);
+    let textures = set.convert(
+        options.from,
+        options.to,
+        options.roughness_curve,
+        options.color.input_colorspace,
+    )?;
+
+    let paths = textures
+        .iter()
+        .map(|texture| {
+            let path = options.naming.path(
+                &set.directory,
+                OutputName {
+                    prefix: texture.prefix,
+                    stem: &set.name,
+                    suffix: texture.suffix,
+                },
+            );
+
+            naming::check_not_input(&path, inputs)?;
 
-    let merged_path = options
-        .metallic_file
-        .with_file_name(format!("{}{}", filename, "MetallicSmoothness.png"));
+            Ok(path)
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    println!("Writing metallic+smoothness file to: {:?}", merged_path);
+    for (texture, path) in textures.into_iter().zip(paths) {
+        progress!("Writing {} texture to: {:?}", texture.description, path);
 
-    metallic_image.save(merged_path)?;
+        let toksvig = texture.normal.as_ref().map(|normal| Toksvig {
+            normal,
+            curve: options.roughness_curve,
+        });
+
+        options.color.save(
+            texture.image,
+            &path,
+            texture.texture,
+            toksvig.as_ref(),
+            &options.output_options,
+        )?;
+    }
 
     Ok(())
 }
 
+fn convert_set(mut options: ConvertSet) -> Result<()> {
+    debug!("{:?}", options);
+
+    let files = batch::expand(&options.paths, options.recursive, image_file::is_image)?;
+    options.naming.set_inputs(&files);
+
+    let sets = texture_set::group(&files, &options.naming.input_suffixes);
+
+    progress!(
+        "Converting {} texture sets from {} to {}...",
+        sets.len(),
+        options.from.name,
+        options.to.name
+    );
+
+    batch::summarise(batch::run(
+        &sets,
+        |set| set.directory.join(&set.name).display().to_string(),
+        |set| convert_texture_set(set, &files, &options),
+    ))
+}
+
+#[derive(Debug, StructOpt)]
+/// Check texture files for values which are not physically plausible, and
+/// will look wrong in an engine.
+///
+/// Base colour should stay within the albedo of real materials, from 30 to
+/// 240 in sRGB. Metallic should be either metal or not, with few values
+/// from 0.2 to 0.8. Roughness should not be 0 everywhere, and packed
+/// textures holding smoothness in alpha must have an alpha channel. Packed
+/// textures are split and each of their maps checked.
+///
+/// Texture files are recognised by their names, such as
+/// `RustyBaseColor.png` or `RustyMetallicSmoothness.png`. Each problem is
+/// reported as a warning or an error, and files with errors fail.
+struct Lint {
+    /// The texture files to check
+    ///
+    /// Directories and glob patterns such as `textures/**/*.png` can be
+    /// given too
+    #[structopt(parse(from_os_str), required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Look for texture files in subdirectories of directories too
+    #[structopt(short, long)]
+    recursive: bool,
+
+    /// How smoothness in packed textures relates to roughness
+    ///
+    /// One of `linear` for smoothness of one minus roughness, `squared` (or
+    /// `perceptual`) for roughness maps holding squared "alpha" roughness,
+    /// or `phong` (or `beckmann`) for smoothness holding a Blinn-Phong
+    /// specular exponent
+    #[structopt(
+        long,
+        default_value = "linear",
+        possible_values = curve::NAMES
+    )]
+    roughness_curve: RoughnessCurve,
+
+    /// A suffix input files use for a map or packed texture, as
+    /// `<kind>=<suffix>`, such as `roughness=_rough` or `unity=_MS`
+    ///
+    /// Can be given more than once
+    #[structopt(long = "input-suffix", number_of_values = 1)]
+    input_suffixes: Vec<InputSuffix>,
+
+    /// The colour space input files are stored in, either `srgb` or
+    /// `linear`
+    ///
+    /// Without one, it is worked out as it is for other commands
+    #[structopt(long, possible_values = color::NAMES)]
+    input_colorspace: Option<ColorSpace>,
+}
+
+fn lint_file(file: &Path, options: &Lint) -> Result<()> {
+    let texture = match texture_set::recognise(file, &options.input_suffixes) {
+        Some((_, texture, _)) => texture,
+        None => bail!("Cannot tell what {:?} holds from its name", file),
+    };
+
+    report::input(file);
+
+    let image = color::open(file, texture.color_space(), options.input_colorspace)?;
+    let issues = lint::check(texture, image, options.roughness_curve);
+
+    // Printed at once, so that files checked at the same time do not mix
+    let mut message = format!("Checked {:?}: ", file);
+
+    if issues.is_empty() {
+        message.push_str("no problems found");
+    } else {
+        message.push_str(&format!("{} problems found", issues.len()));
+    }
+
+    for issue in &issues {
+        message.push_str(&format!("\n  {:<8} {}", issue.severity, issue.message));
+        report::issue(issue.severity.name(), &issue.message);
+    }
+
+    progress!("{}", message);
+
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .count();
+
+    if errors > 0 {
+        bail!("{} errors found", errors);
+    }
+
+    Ok(())
+}
+
+fn lint(options: Lint) -> Result<()> {
+    debug!("{:?}", options);
+
+    let files = batch::expand(&options.paths, options.recursive, |file| {
+        image_file::is_image(file)
+            && texture_set::recognise(file, &options.input_suffixes).is_some()
+    })?;
+
+    batch::summarise(batch::run(
+        &files,
+        |file| file.display().to_string(),
+        |file| lint_file(file, &options),
+    ))
+}
+
 /// Convert physically-based rendering textures between Unity-style combined
 /// metallic and smoothness file and Pixar USD-style separate metallic and
 /// roughness files
+///
+/// Packed textures can use any of these layouts:
+///
+/// - `unity`: Unity Standard shader `NameMetallicSmoothness`, with metallic
+///   in red, green and blue and smoothness in alpha
+///
+/// - `hdrp`: Unity High Definition Render Pipeline `NameMaskMap`, with
+///   metallic in red, occlusion in green, detail mask in blue and
+///   smoothness in alpha
+///
+/// - `gltf`: glTF `NameMetallicRoughness`, with roughness in green and
+///   metallic in blue
+///
+/// - `gltf-orm`: glTF `NameOcclusionRoughnessMetallic`, with occlusion in
+///   red, roughness in green and metallic in blue
+///
+/// - `unreal-orm`: Unreal Engine `T_Name_ORM`, with occlusion in red,
+///   roughness in green and metallic in blue
+///
+/// - `unreal-rma`: Unreal Engine `T_Name_RMA`, with roughness in red,
+///   metallic in green and occlusion in blue
+///
+/// Textures can be PNG, TGA, TIFF and other common image files, OpenEXR
+/// files and Radiance HDR files. One layer of a multi-layer EXR file is
+/// given as `<file>.exr#<layer>`, and directories of them are searched
+/// layer by layer, but EXR output files always have a single layer. Output
+/// files can also be KTX2 and block-compressed DDS
+/// files, which hold every mip level with `--mipmaps`.
+///
+/// Exits with 0 on success, 1 if an input or option is not valid, 2 if the
+/// command line is not valid, 3 if a file cannot be read or written, and 4
+/// if only some of the files of a batch failed.
 #[derive(Debug, StructOpt)]
-enum Args {
+struct Args {
+    /// How to report what was done, either `text` or `json`
+    ///
+    /// `json` prints a single JSON document once everything is done,
+    /// instead of progress messages. It has a record for each file or set
+    /// processed, with the files read, the files written with their size
+    /// and the range and mean of each of their channels, any warnings, and
+    /// the error if it failed. The document ends with how many succeeded
+    /// and failed, the error the command failed with and its exit code
+    #[structopt(
+        long,
+        global = true,
+        default_value = "text",
+        possible_values = report::NAMES
+    )]
+    format: report::Format,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
     Split(Split),
     Merge(Merge),
+    Convert(Convert),
+    ConvertSet(ConvertSet),
+    Pack(Pack),
+    #[structopt(name = "spec2metal")]
+    Spec2Metal(Spec2Metal),
+    #[structopt(name = "metal2spec")]
+    Metal2Spec(Metal2Spec),
+    Normal(Normal),
+    Lint(Lint),
+}
+
+/// Runs a command which processes a single item, recording what it does
+fn single(item: &Path, command: impl FnOnce() -> Result<()>) -> Result<()> {
+    report::record(0, item.display().to_string(), command)
 }
 
-fn main() -> Result<()> {
-    env_logger::init();
+fn main() -> ExitCode {
+    report::init_logger();
 
-    let args = Args::from_args();
+    let args = match Args::from_args_safe() {
+        Ok(args) => args,
+        Err(error) if error.use_stderr() => {
+            eprintln!("{}", error.message);
+            return ExitCode::from(2);
+        }
+        Err(error) => error.exit(),
+    };
 
     debug!("args: {:?}", args);
 
-    match args {
-        Args::Split(options) => split(options),
-        Args::Merge(options) => merge(options),
+    report::set_format(args.format);
+
+    let outcome = match args.command {
+        Command::Split(options) => split(options),
+        Command::Merge(options) => merge(options),
+        Command::Convert(options) => single(&options.file.clone(), || convert(options)),
+        Command::ConvertSet(options) => convert_set(options),
+        Command::Pack(options) => single(&options.output.clone(), || pack(options)),
+        Command::Spec2Metal(options) => {
+            single(&options.diffuse_file.clone(), || spec2metal(options))
+        }
+        Command::Metal2Spec(options) => {
+            single(&options.base_color_file.clone(), || metal2spec(options))
+        }
+        Command::Normal(options) => single(&options.file.clone(), || normal(options)),
+        Command::Lint(options) => lint(options),
+    };
+
+    report::finish(&outcome);
+
+    if let Err(error) = &outcome {
+        if !report::is_json() {
+            eprintln!("Error: {:?}", error);
+        }
     }
+
+    ExitCode::from(report::exit_code(&outcome))
 }
@@ -0,0 +1,232 @@
+use crate::color::{self, ColorSpace};
+use crate::curve::RoughnessCurve;
+use crate::depth;
+use crate::resize;
+use image::imageops::{self, FilterType};
+use image::{ColorType, DynamicImage, Rgba, Rgba32FImage};
+
+/// The normal map of a material, for widening the roughness of its packed
+/// texture in smaller mip levels
+///
+/// Averaging bumpy normals gives shorter normals, which Toksvig's method
+/// turns into extra roughness. Bumps which a smaller level can no longer
+/// show still spread its highlights, so distant surfaces do not become
+/// shinier than they are up close, or sparkle.
+pub struct Toksvig<'a> {
+    pub normal: &'a DynamicImage,
+    /// How smoothness and roughness values relate to "alpha" roughness
+    pub curve: RoughnessCurve,
+}
+
+/// Halves the size of an image, averaging each block of 2x2 pixels
+///
+/// Odd sizes are rounded down, and sizes of 1 stay 1.
+fn downsample(image: &Rgba32FImage) -> Rgba32FImage {
+    let (width, height) = image.dimensions();
+    let (half_width, half_height) = ((width / 2).max(1), (height / 2).max(1));
+
+    Rgba32FImage::from_fn(half_width, half_height, |x_position, y_position| {
+        let mut sum = [0.0; 4];
+
+        for (x_offset, y_offset) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let pixel = image.get_pixel(
+                (x_position * 2 + x_offset).min(width - 1),
+                (y_position * 2 + y_offset).min(height - 1),
+            );
+
+            for (total, value) in sum.iter_mut().zip(pixel.0) {
+                *total += value;
+            }
+        }
+
+        Rgba(sum.map(|total| total / 4.0))
+    })
+}
+
+/// Decodes the normals of a normal map to unit vectors, at the size of the
+/// texture they are for
+///
+/// Z is rebuilt from X and Y, so that normal maps without it work too.
+fn unit_normals(normal: &DynamicImage, width: u32, height: u32) -> Rgba32FImage {
+    let mut normals = normal.to_rgba32f();
+
+    if normals.dimensions() != (width, height) {
+        debug!(
+            "Resizing {}x{} normal map to {}x{} for roughness mip levels",
+            normals.width(),
+            normals.height(),
+            width,
+            height
+        );
+
+        normals = imageops::resize(&normals, width, height, FilterType::Triangle);
+    }
+
+    for pixel in normals.pixels_mut() {
+        let (mut x, mut y) = (pixel[0] * 2.0 - 1.0, pixel[1] * 2.0 - 1.0);
+        let length = (x * x + y * y).sqrt();
+
+        if length > 1.0 {
+            x /= length;
+            y /= length;
+        }
+
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+
+        pixel.0 = [x, y, z, 1.0];
+    }
+
+    normals
+}
+
+/// Widens the roughness in one channel of a mip level by how much its
+/// averaged normals are shorter than unit length
+///
+/// Toksvig's estimate of the variance of the normals, `(1 - |n|) / |n|`, is
+/// added to the squared "alpha" roughness of each pixel.
+fn widen_roughness(
+    level: &mut Rgba32FImage,
+    normals: &Rgba32FImage,
+    (channel, smoothness): (usize, bool),
+    curve: RoughnessCurve,
+) {
+    for (pixel, normal) in level.pixels_mut().zip(normals.pixels()) {
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2])
+            .sqrt()
+            .clamp(f32::EPSILON, 1.0);
+        let variance = (1.0 - length) / length;
+
+        let roughness = if smoothness {
+            curve.roughness(pixel[channel])
+        } else {
+            pixel[channel]
+        };
+
+        let alpha = curve.alpha(roughness);
+        let widened = curve.roughness_from_alpha((alpha * alpha + 2.0 * variance).sqrt());
+
+        pixel[channel] = if smoothness {
+            curve.smoothness(widened)
+        } else {
+            widened
+        };
+    }
+}
+
+/// Converts a level back to the kind of image it was made from
+///
+/// Greyscale images are made from the brightness of red, green and blue, so
+/// roughness widened in red is copied to green and blue first, to keep it
+/// from being blended with the roughness it was widened from.
+fn to_color(mut level: Rgba32FImage, color: ColorType) -> DynamicImage {
+    if color.channel_count() <= 2 {
+        for pixel in level.pixels_mut() {
+            pixel[1] = pixel[0];
+            pixel[2] = pixel[0];
+        }
+    }
+
+    depth::convert(DynamicImage::ImageRgba32F(level), color)
+}
+
+/// Widens the roughness of a texture which is smaller than its normal map,
+/// such as one shrunk for mobile, by the detail of the normal map it can
+/// no longer show
+///
+/// The normal map is averaged down to the size of the texture, as smaller
+/// mip levels are. `roughness` gives the channel holding roughness, and
+/// whether it is stored as smoothness. Textures at least as large as their
+/// normal map are left as they are.
+pub fn widen(image: &DynamicImage, roughness: (usize, bool), toksvig: &Toksvig) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    let (normal_width, normal_height) = (toksvig.normal.width(), toksvig.normal.height());
+
+    if normal_width <= width && normal_height <= height {
+        return image.clone();
+    }
+
+    debug!(
+        "Widening roughness of {}x{} texture with {}x{} normal map",
+        width, height, normal_width, normal_height
+    );
+
+    let normals = resize::box_resize(
+        &unit_normals(toksvig.normal, normal_width, normal_height),
+        width,
+        height,
+    );
+
+    let mut level = image.to_rgba32f();
+    widen_roughness(&mut level, &normals, roughness, toksvig.curve);
+
+    to_color(level, image.color())
+}
+
+/// Makes every mip level of an image, from the image itself down to 1x1
+///
+/// The colour channels of sRGB images are averaged as linear light, so that
+/// smaller levels do not get darker. Every level is the same kind of image
+/// as the original.
+///
+/// If `roughness` gives the channel holding roughness, and whether it is
+/// stored as smoothness, and `toksvig` gives the matching normal map, the
+/// roughness of each smaller level is widened by the detail it loses.
+pub fn generate(
+    image: &DynamicImage,
+    color_space: Option<ColorSpace>,
+    roughness: Option<(usize, bool)>,
+    toksvig: Option<&Toksvig>,
+) -> Vec<DynamicImage> {
+    let srgb = color_space == Some(ColorSpace::Srgb);
+    let mut level = image.to_rgba32f();
+
+    if srgb {
+        for pixel in level.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                *channel = color::srgb_to_linear(*channel);
+            }
+        }
+    }
+
+    let (width, height) = level.dimensions();
+
+    let mut normals = match (roughness, toksvig) {
+        (Some(_), Some(toksvig)) => Some(unit_normals(toksvig.normal, width, height)),
+        _ => None,
+    };
+
+    let mut levels = Vec::new();
+
+    loop {
+        let next = downsample(&level);
+        let (width, height) = level.dimensions();
+
+        let mut encoded = level;
+
+        if srgb {
+            for pixel in encoded.pixels_mut() {
+                for channel in &mut pixel.0[..3] {
+                    *channel = color::linear_to_srgb(*channel);
+                }
+            }
+        }
+
+        // Smaller levels are made from levels whose roughness was not
+        // widened, as their normals already hold all the detail lost since
+        // the first level
+        if let (Some(roughness), Some(toksvig), Some(normals)) = (roughness, toksvig, &normals) {
+            if !levels.is_empty() {
+                widen_roughness(&mut encoded, normals, roughness, toksvig.curve);
+            }
+        }
+
+        levels.push(to_color(encoded, image.color()));
+
+        if width == 1 && height == 1 {
+            return levels;
+        }
+
+        normals = normals.as_ref().map(downsample);
+        level = next;
+    }
+}
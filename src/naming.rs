@@ -0,0 +1,230 @@
+use anyhow::{anyhow, bail, Result};
+use matknife::image_file;
+use matknife::texture_set::{InputSuffix, Texture};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// The placeholders a name template can use
+const PLACEHOLDERS: &[&str] = &["prefix", "stem", "suffix", "map", "ext"];
+
+/// A template for the names of output files, such as `{stem}_{map}.{ext}`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl FromStr for NameTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(template: &str) -> Result<Self> {
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("Unclosed placeholder in name template {:?}", template))?;
+            let placeholder = &rest[start + 1..start + end];
+
+            if !PLACEHOLDERS.contains(&placeholder) {
+                bail!(
+                    "Unknown placeholder {{{}}} in name template {:?}, expected one of {}",
+                    placeholder,
+                    template,
+                    PLACEHOLDERS
+                        .iter()
+                        .map(|placeholder| format!("{{{}}}", placeholder))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+
+            rest = &rest[start + end + 1..];
+        }
+
+        Ok(NameTemplate(template.to_string()))
+    }
+}
+
+/// The parts of the name of an output file
+#[derive(Clone, Copy, Debug)]
+pub struct OutputName<'a> {
+    /// Comes before the stem, such as `T_` in `T_Rusty_ORM`
+    pub prefix: &'a str,
+    /// The name shared by every texture file in a set, such as `Rusty`
+    pub stem: &'a str,
+    /// Says what the file holds, such as `Metallic` or `_ORM`
+    pub suffix: &'a str,
+}
+
+/// Options for where output files are written and what they are called
+#[derive(Debug, StructOpt)]
+pub struct NamingOptions {
+    /// Write output files to this directory, rather than next to the input
+    /// files
+    ///
+    /// Input files in subdirectories, such as those found with
+    /// `--recursive`, are written to the same subdirectories of it, so that
+    /// sets with the same name in different directories do not overwrite
+    /// each other
+    #[structopt(long, parse(from_os_str))]
+    pub output_dir: Option<PathBuf>,
+
+    /// How to name output files
+    ///
+    /// `{stem}` is the name shared by the texture files of a set, `{map}`
+    /// is what the file holds, such as `Roughness` or `ORM`, and `{ext}` is
+    /// the file extension. `{prefix}` and `{suffix}` are the prefix and
+    /// suffix the layout or convention would normally use, such as `T_` and
+    /// `_ORM`
+    #[structopt(long, default_value = "{prefix}{stem}{suffix}.{ext}")]
+    pub name_template: NameTemplate,
+
+    /// The file extension of output files, which also decides their format
+    #[structopt(long, default_value = "png")]
+    pub extension: String,
+
+    /// A suffix input files use for a map or packed texture, as
+    /// `<kind>=<suffix>`, such as `roughness=_rough` or `unity=_MS`
+    ///
+    /// Can be given more than once. Kinds of map are metallic, roughness,
+    /// occlusion, detail-mask, base-color, diffuse, specular-glossiness,
+    /// normal, height and emission, and kinds of packed texture are the
+    /// names of layouts
+    #[structopt(long = "input-suffix", number_of_values = 1)]
+    pub input_suffixes: Vec<InputSuffix>,
+
+    /// The directory holding every input file of a batch, which the
+    /// subdirectories under `--output-dir` are worked out from
+    #[structopt(skip)]
+    root: Option<PathBuf>,
+}
+
+/// The full path of a directory, where an empty path is the current
+/// directory
+fn canonical_directory(directory: &Path) -> Option<PathBuf> {
+    if directory.as_os_str().is_empty() {
+        Path::new(".").canonicalize().ok()
+    } else {
+        directory.canonicalize().ok()
+    }
+}
+
+impl NamingOptions {
+    /// Finds the name shared by a set of texture files from the name of one
+    /// of them, by removing its extension and the prefix and suffix saying
+    /// it holds `texture`
+    ///
+    /// Suffixes given with `--input-suffix` are tried before `prefix` and
+    /// `suffix`.
+    pub fn stem(
+        &self,
+        file: &Path,
+        texture: Texture,
+        prefix: &str,
+        suffix: &str,
+    ) -> Result<String> {
+        let file_stem = image_file::stem(file)?;
+
+        for input_suffix in &self.input_suffixes {
+            if input_suffix.texture == texture {
+                if let Some(stem) = file_stem.strip_suffix(input_suffix.suffix.as_str()) {
+                    return Ok(stem.to_string());
+                }
+            }
+        }
+
+        let mut filename: String = file_stem.to_string();
+
+        if let Some(basename) = filename.strip_prefix(prefix) {
+            filename = basename.to_string();
+        }
+
+        if let Some(basename) = filename.strip_suffix(suffix) {
+            filename = basename.to_string();
+        }
+
+        Ok(filename)
+    }
+
+    /// Remembers the input files of a batch, so that output files keep
+    /// the subdirectories of their input files under `--output-dir`
+    pub fn set_inputs(&mut self, files: &[PathBuf]) {
+        let mut root: Option<PathBuf> = None;
+
+        for file in files {
+            let (file, _) = image_file::split_layer(file);
+            let directory = match canonical_directory(file.parent().unwrap_or(Path::new(""))) {
+                Some(directory) => directory,
+                None => return,
+            };
+
+            root = Some(match root {
+                Some(root) => root
+                    .components()
+                    .zip(directory.components())
+                    .take_while(|(root, directory)| root == directory)
+                    .map(|(root, _)| root)
+                    .collect(),
+                None => directory,
+            });
+        }
+
+        self.root = root;
+    }
+
+    /// Works out where to write an output file for the input files in
+    /// `directory`
+    pub fn path(&self, directory: &Path, name: OutputName) -> PathBuf {
+        let map = name.suffix.trim_start_matches(['_', '-', ' ', '.']);
+
+        let file_name = self
+            .name_template
+            .0
+            .replace("{prefix}", name.prefix)
+            .replace("{stem}", name.stem)
+            .replace("{suffix}", name.suffix)
+            .replace("{map}", map)
+            .replace("{ext}", &self.extension);
+
+        let output_dir = match &self.output_dir {
+            Some(output_dir) => output_dir,
+            None => return directory.join(file_name),
+        };
+
+        let subdirectory = self.root.as_ref().and_then(|root| {
+            canonical_directory(directory)?
+                .strip_prefix(root)
+                .ok()
+                .map(Path::to_path_buf)
+        });
+
+        output_dir
+            .join(subdirectory.unwrap_or_default())
+            .join(file_name)
+    }
+}
+
+/// Fails if an output file would be written over one of the input files
+///
+/// Output files are written next to the input files unless `--output-dir`
+/// is given, so a convention which names a texture the same way as the
+/// input convention would otherwise replace the original file.
+pub fn check_not_input(path: &Path, inputs: &[PathBuf]) -> Result<()> {
+    let output = match path.canonicalize() {
+        Ok(output) => output,
+        Err(_) => return Ok(()),
+    };
+
+    for input in inputs {
+        let (file, _) = image_file::split_layer(input);
+
+        if file.canonicalize().is_ok_and(|file| file == output) {
+            bail!(
+                "Writing {:?} would overwrite the input file {:?}! Use --output-dir or --name-template to write it elsewhere",
+                path,
+                input
+            );
+        }
+    }
+
+    Ok(())
+}
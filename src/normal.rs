@@ -0,0 +1,140 @@
+use crate::depth;
+use anyhow::{bail, Result};
+use image::{DynamicImage, Rgba};
+use std::fmt;
+use std::str::FromStr;
+
+/// Which way the green channel of a normal map points
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalConvention {
+    /// Green points up (Y+), as in OpenGL, Unity, Blender and USD
+    OpenGl,
+    /// Green points down (Y-), as in DirectX and Unreal Engine
+    DirectX,
+}
+
+/// The command line names of every normal map convention
+pub const NAMES: &[&str] = &["opengl", "gl", "directx", "dx"];
+
+impl NormalConvention {
+    /// The suffix of file names holding a normal map in this convention,
+    /// such as `_OpenGL` in `RustyNormal_OpenGL.png`
+    pub fn suffix(self) -> &'static str {
+        match self {
+            NormalConvention::OpenGl => "_OpenGL",
+            NormalConvention::DirectX => "_DirectX",
+        }
+    }
+}
+
+impl FromStr for NormalConvention {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "opengl" | "gl" => Ok(NormalConvention::OpenGl),
+            "directx" | "dx" => Ok(NormalConvention::DirectX),
+            _ => bail!(
+                "Unknown normal map convention {:?}, expected one of {}",
+                name,
+                NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for NormalConvention {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            NormalConvention::OpenGl => "OpenGL",
+            NormalConvention::DirectX => "DirectX",
+        })
+    }
+}
+
+/// What to do to the vectors of a normal map while converting it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NormalOptions {
+    /// Scale every vector back to unit length
+    pub renormalize: bool,
+    /// Ignore the blue channel and work Z out from X and Y, for two-channel
+    /// normal maps such as BC5 compressed ones
+    pub reconstruct_z: bool,
+    /// Leave Z out of the converted normal map, writing black to blue, for
+    /// two-channel normal maps such as BC5 compressed ones
+    pub drop_z: bool,
+}
+
+fn decode(value: f32) -> f32 {
+    value * 2.0 - 1.0
+}
+
+fn encode(value: f32) -> f32 {
+    (value.clamp(-1.0, 1.0) + 1.0) / 2.0
+}
+
+/// Converts a normal map from one convention to another, adjusting its
+/// vectors as asked along the way
+///
+/// Alpha is kept as-is, for normal maps which store something else in it.
+/// The converted normal map is as precise as the original.
+pub fn convert(
+    image: &DynamicImage,
+    from: NormalConvention,
+    to: NormalConvention,
+    options: NormalOptions,
+) -> DynamicImage {
+    let mut converted = image.to_rgba32f();
+
+    for pixel in converted.pixels_mut() {
+        let mut x = decode(pixel[0]);
+        let mut y = decode(pixel[1]);
+        let mut z = decode(pixel[2]);
+
+        if from != to {
+            y = -y;
+        }
+
+        if options.reconstruct_z {
+            let length_squared = x * x + y * y;
+
+            if length_squared > 1.0 {
+                let length = length_squared.sqrt();
+
+                x /= length;
+                y /= length;
+            }
+
+            z = (1.0 - x * x - y * y).max(0.0).sqrt();
+        }
+
+        if options.renormalize {
+            let length = (x * x + y * y + z * z).sqrt();
+
+            if length > f32::EPSILON {
+                x /= length;
+                y /= length;
+                z /= length;
+            } else {
+                x = 0.0;
+                y = 0.0;
+                z = 1.0;
+            }
+        }
+
+        *pixel = Rgba([
+            encode(x),
+            encode(y),
+            if options.drop_z { 0.0 } else { encode(z) },
+            pixel[3],
+        ]);
+    }
+
+    let color = if image.color().has_alpha() {
+        depth::BitDepth::of(image).color_type(4)
+    } else {
+        depth::BitDepth::of(image).color_type(3)
+    };
+
+    depth::convert(DynamicImage::ImageRgba32F(converted), color)
+}
@@ -0,0 +1,132 @@
+use crate::report;
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use matknife::color::ColorSpace;
+use matknife::export::ExportOptions;
+use matknife::mipmap::Toksvig;
+use matknife::resize::{self, PowerOfTwo};
+use matknife::texture_set::Texture;
+use matknife::{dds, ktx2};
+use std::path::Path;
+use structopt::StructOpt;
+
+/// Options for how output files are encoded
+#[derive(Debug, StructOpt)]
+pub struct OutputOptions {
+    /// Write every mip level down to 1x1 to output files whose format can
+    /// hold them, such as KTX2 and DDS
+    #[structopt(long)]
+    pub mipmaps: bool,
+
+    /// How to encode KTX2 output files, one of `none`, `uastc` or `etc1s`
+    ///
+    /// UASTC and ETC1S are Basis Universal's encodings, which web viewers
+    /// can transcode to whatever the GPU supports. They are encoded by
+    /// `toktx` from KTX-Software 4 or later, which must be installed, or
+    /// given by the MATKNIFE_TOKTX environment variable
+    #[structopt(long, default_value = "none", possible_values = ktx2::NAMES)]
+    pub ktx2_encoding: ktx2::Encoding,
+
+    /// How to store the pixels of DDS output files, one of `auto`, `bc1`,
+    /// `bc3`, `bc4`, `bc5`, `bc7` or `rgba`
+    ///
+    /// With `auto`, single-channel maps such as roughness and metallic use
+    /// BC4, normal maps BC5, packed maps BC7, and colour maps BC1, or BC7 if
+    /// they have alpha
+    #[structopt(long, default_value = "auto", possible_values = dds::NAMES)]
+    pub dds_format: dds::Format,
+
+    /// Do not widen the roughness or smoothness of packed textures in
+    /// smaller mip levels, or when they are shrunk
+    ///
+    /// Otherwise, when a texture set has a normal map, the roughness of each
+    /// mip level is widened by how much detail of the normal map it loses
+    /// (Toksvig's method), so that distant surfaces do not look too shiny.
+    /// Textures shrunk with `--downscale`, `--max-size` or `--power-of-two`
+    /// are widened the same way
+    #[structopt(long)]
+    pub no_toksvig: bool,
+
+    /// The largest width or height to write output files at
+    ///
+    /// Larger textures are shrunk to fit, keeping their shape
+    #[structopt(long, parse(try_from_str = parse_size))]
+    pub max_size: Option<u32>,
+
+    /// Round the width and height of output files to powers of two, one of
+    /// `up`, `down` or `nearest`
+    ///
+    /// Sizes are never rounded up past `--max-size`
+    #[structopt(long, possible_values = resize::POWER_OF_TWO_NAMES)]
+    pub power_of_two: Option<PowerOfTwo>,
+
+    /// Shrink output files by a factor, such as 4 to write 1024x1024 files
+    /// from 4096x4096 textures
+    ///
+    /// Colour maps are resized with Lanczos filtering in linear light, and
+    /// other maps, such as roughness and packed textures, are averaged as
+    /// they are stored. Normals are made unit length again after averaging
+    #[structopt(long, parse(try_from_str = parse_factor))]
+    pub downscale: Option<f32>,
+}
+
+fn parse_size(size: &str) -> Result<u32> {
+    match size.parse()? {
+        0 => bail!("Output files cannot have a size of 0"),
+        size => Ok(size),
+    }
+}
+
+fn parse_factor(factor: &str) -> Result<f32> {
+    let factor: f32 = factor.parse()?;
+
+    if !(factor >= 1.0 && factor.is_finite()) {
+        bail!(
+            "Output files can only be shrunk, by a factor of at least 1, not {}",
+            factor
+        );
+    }
+
+    Ok(factor)
+}
+
+impl OutputOptions {
+    /// The options for writing files, as the library takes them
+    pub fn export(&self) -> ExportOptions {
+        ExportOptions {
+            mipmaps: self.mipmaps,
+            ktx2_encoding: self.ktx2_encoding,
+            dds_format: self.dds_format,
+            no_toksvig: self.no_toksvig,
+            max_size: self.max_size,
+            power_of_two: self.power_of_two,
+            downscale: self.downscale,
+        }
+    }
+
+    /// Whether mip levels, or textures shrunk by the options, are written
+    /// with roughness widened by the normal map, so that the normal map is
+    /// needed
+    pub fn toksvig(&self) -> bool {
+        self.export().toksvig()
+    }
+
+    /// Writes an image to an output file, as [`ExportOptions::save`] does,
+    /// and reports it
+    pub fn save(
+        &self,
+        image: &DynamicImage,
+        path: &Path,
+        color_space: Option<ColorSpace>,
+        texture: Option<Texture>,
+        toksvig: Option<&Toksvig>,
+    ) -> Result<()> {
+        let written = self
+            .export()
+            .save(image, path, color_space, texture, toksvig)?;
+
+        report::output(path, texture, &written);
+
+        Ok(())
+    }
+}
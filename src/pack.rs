@@ -0,0 +1,266 @@
+use crate::depth::BitDepth;
+use anyhow::{anyhow, bail, Result};
+use image::{DynamicImage, GenericImageView, Pixel, Rgba, Rgba32FImage};
+use std::fmt;
+use std::str::FromStr;
+
+/// A single channel of an input image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    /// The perceptual brightness of the red, green and blue channels
+    Luma,
+    /// The average of the red, green and blue channels
+    Average,
+    /// The brightest of the red, green and blue channels
+    Max,
+}
+
+impl Channel {
+    fn read(self, pixel: &Rgba<f32>) -> f32 {
+        match self {
+            Channel::Red => pixel[0],
+            Channel::Green => pixel[1],
+            Channel::Blue => pixel[2],
+            Channel::Alpha => pixel[3],
+            Channel::Luma => pixel.to_luma()[0],
+            Channel::Average => (pixel[0] + pixel[1] + pixel[2]) / 3.0,
+            Channel::Max => pixel[0].max(pixel[1]).max(pixel[2]),
+        }
+    }
+
+    /// The position of the channel in an RGBA pixel, which channels made
+    /// from several others have none of
+    pub fn index(self) -> Option<usize> {
+        match self {
+            Channel::Red => Some(0),
+            Channel::Green => Some(1),
+            Channel::Blue => Some(2),
+            Channel::Alpha => Some(3),
+            Channel::Luma | Channel::Average | Channel::Max => None,
+        }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "r" | "red" => Ok(Channel::Red),
+            "g" | "green" => Ok(Channel::Green),
+            "b" | "blue" => Ok(Channel::Blue),
+            "a" | "alpha" => Ok(Channel::Alpha),
+            "l" | "luma" | "luminance" => Ok(Channel::Luma),
+            "avg" | "average" => Ok(Channel::Average),
+            "max" => Ok(Channel::Max),
+            _ => bail!(
+                "Unknown channel {:?}, expected one of r, g, b, a, l, avg or max",
+                name
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            Channel::Red => "r",
+            Channel::Green => "g",
+            Channel::Blue => "b",
+            Channel::Alpha => "a",
+            Channel::Luma => "l",
+            Channel::Average => "avg",
+            Channel::Max => "max",
+        })
+    }
+}
+
+/// Where the value of one output channel comes from
+///
+/// Written on the command line as `<input>:<channel>` (for example `0:r`
+/// for the red channel of the first input), `<input>:<channel>:invert` to
+/// flip the value, or a plain number from 0 to 255 for a constant fill
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelSource {
+    /// Read a channel of one of the input images
+    Input {
+        index: usize,
+        channel: Channel,
+        invert: bool,
+    },
+    /// Fill the channel with the same value everywhere
+    Constant(u8),
+}
+
+impl ChannelSource {
+    /// Reads the given channel of the input image at `index`, as-is
+    pub const fn input(index: usize, channel: Channel) -> Self {
+        ChannelSource::Input {
+            index,
+            channel,
+            invert: false,
+        }
+    }
+
+    /// Reads the given channel of the input image at `index`, flipping
+    /// black and white
+    pub const fn inverted(index: usize, channel: Channel) -> Self {
+        ChannelSource::Input {
+            index,
+            channel,
+            invert: true,
+        }
+    }
+}
+
+impl FromStr for ChannelSource {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        if let Ok(value) = source.parse::<u8>() {
+            return Ok(ChannelSource::Constant(value));
+        }
+
+        let mut parts = source.split(':');
+
+        let index = parts
+            .next()
+            .unwrap_or_default()
+            .parse::<usize>()
+            .map_err(|_| anyhow!("Invalid channel source {:?}", source))?;
+
+        let channel = parts
+            .next()
+            .ok_or_else(|| anyhow!("Channel source {:?} is missing a channel", source))?
+            .parse::<Channel>()?;
+
+        let invert = match parts.next() {
+            None => false,
+            Some("invert") => true,
+            Some(modifier) => bail!("Unknown channel modifier {:?}", modifier),
+        };
+
+        if parts.next().is_some() {
+            bail!("Invalid channel source {:?}", source);
+        }
+
+        Ok(ChannelSource::Input {
+            index,
+            channel,
+            invert,
+        })
+    }
+}
+
+impl fmt::Display for ChannelSource {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChannelSource::Input {
+                index,
+                channel,
+                invert: false,
+            } => write!(formatter, "{}:{}", index, channel),
+            ChannelSource::Input {
+                index,
+                channel,
+                invert: true,
+            } => write!(formatter, "{}:{}:invert", index, channel),
+            ChannelSource::Constant(value) => write!(formatter, "{}", value),
+        }
+    }
+}
+
+/// Whether the red, green and blue channels of an image hold the same
+/// values, as in greyscale images stored as colour ones
+///
+/// Values count as the same if they are within half an 8-bit step.
+pub fn is_greyscale(image: &DynamicImage) -> bool {
+    if !image.color().has_color() {
+        return true;
+    }
+
+    image.to_rgba32f().pixels().all(|pixel| {
+        let (low, high) = (
+            pixel[0].min(pixel[1]).min(pixel[2]),
+            pixel[0].max(pixel[1]).max(pixel[2]),
+        );
+
+        high - low < 0.5 / 255.0
+    })
+}
+
+/// Builds a new image by reading each of its channels from a channel of
+/// one of the input images, or from a constant.
+///
+/// The number of sources decides the kind of image produced: one source
+/// makes a greyscale image, two a greyscale image with alpha, three an RGB
+/// image and four an RGBA image. The image is as precise as the most
+/// precise input, so 16-bit and float inputs make 16-bit and float images.
+pub fn pack(inputs: &[DynamicImage], sources: &[ChannelSource]) -> Result<DynamicImage> {
+    if sources.is_empty() || sources.len() > 4 {
+        bail!(
+            "Output images must have between 1 and 4 channels, not {}",
+            sources.len()
+        );
+    }
+
+    let first = inputs
+        .first()
+        .ok_or_else(|| anyhow!("At least one input image is required!"))?;
+
+    if inputs
+        .iter()
+        .any(|input| input.dimensions() != first.dimensions())
+    {
+        bail!("Input images are not the same size!");
+    }
+
+    for source in sources {
+        if let ChannelSource::Input { index, .. } = source {
+            if *index >= inputs.len() {
+                bail!(
+                    "Channel source {} refers to input {}, but there are only {} inputs",
+                    source,
+                    index,
+                    inputs.len()
+                );
+            }
+        }
+    }
+
+    let depth = BitDepth::deepest(inputs);
+    let inputs: Vec<Rgba32FImage> = inputs.iter().map(DynamicImage::to_rgba32f).collect();
+    let (width, height) = first.dimensions();
+
+    let mut output = vec![0.0; width as usize * height as usize * sources.len()];
+
+    for (pixel_index, output_pixel) in output.chunks_exact_mut(sources.len()).enumerate() {
+        let x_position = pixel_index as u32 % width;
+        let y_position = pixel_index as u32 / width;
+
+        for (output_channel, source) in output_pixel.iter_mut().zip(sources) {
+            *output_channel = match *source {
+                ChannelSource::Input {
+                    index,
+                    channel,
+                    invert,
+                } => {
+                    let value = channel.read(inputs[index].get_pixel(x_position, y_position));
+
+                    if invert {
+                        1.0 - value
+                    } else {
+                        value
+                    }
+                }
+                ChannelSource::Constant(value) => value as f32 / 255.0,
+            };
+        }
+    }
+
+    depth.image(width, height, sources.len(), &output)
+}
@@ -0,0 +1,491 @@
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use log::{Level, LevelFilter, Log, Metadata};
+use matknife::texture_set::Texture;
+use std::cell::RefCell;
+use std::fmt::{self, Write};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Prints a progress message, unless what was done is reported as JSON
+macro_rules! progress {
+    ($($argument:tt)*) => {
+        if !$crate::report::is_json() {
+            println!($($argument)*);
+        }
+    };
+}
+
+/// How to report what was done
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Progress messages and a summary, for people to read
+    Text,
+    /// A single JSON document, for other programs to read
+    Json,
+}
+
+/// The command line names of every report format
+pub const NAMES: &[&str] = &["text", "json"];
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => bail!(
+                "Unknown report format {:?}, expected one of {}",
+                name,
+                NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Sets how what was done is reported, for the rest of the run
+pub fn set_format(format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+/// Whether what was done is reported as JSON
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// What kind of failure a command ended with, which decides its exit code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// An input file or option is not valid for what was asked
+    Validation,
+    /// A file could not be found, read or written
+    Io,
+    /// Some jobs of a batch succeeded and others failed
+    Partial,
+}
+
+impl Kind {
+    /// The exit code for this kind of failure
+    ///
+    /// 2 is left for invalid command lines, which are reported before any
+    /// work starts.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Kind::Validation => 1,
+            Kind::Io => 3,
+            Kind::Partial => 4,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Validation => "validation",
+            Kind::Io => "io",
+            Kind::Partial => "partial",
+        }
+    }
+}
+
+/// The error a batch ends with when any of its jobs failed
+#[derive(Debug)]
+pub struct BatchFailure {
+    pub failed: usize,
+    pub total: usize,
+    /// The kind of failure, which is partial unless every job failed
+    pub kind: Kind,
+}
+
+impl fmt::Display for BatchFailure {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} of {} files failed", self.failed, self.total)
+    }
+}
+
+impl std::error::Error for BatchFailure {}
+
+/// Works out what kind of failure an error is
+///
+/// Errors caused by reading or writing a file are I/O errors, and every
+/// other error is about the inputs or options being invalid.
+pub fn kind(error: &anyhow::Error) -> Kind {
+    if let Some(failure) = error.downcast_ref::<BatchFailure>() {
+        return failure.kind;
+    }
+
+    let io = error.chain().any(|cause| {
+        cause.is::<io::Error>()
+            || matches!(
+                cause.downcast_ref::<image::ImageError>(),
+                Some(image::ImageError::IoError(_))
+            )
+            || matches!(
+                cause.downcast_ref::<exr::error::Error>(),
+                Some(exr::error::Error::Io(_))
+            )
+    });
+
+    if io {
+        Kind::Io
+    } else {
+        Kind::Validation
+    }
+}
+
+/// The range and average of the values in one channel of an output file
+#[derive(Debug)]
+struct ChannelStats {
+    min: f32,
+    max: f32,
+    mean: f32,
+}
+
+/// An output file written by a job
+#[derive(Debug)]
+struct Output {
+    path: PathBuf,
+    /// What the file holds, as named on the command line, if it is known
+    kind: Option<&'static str>,
+    width: u32,
+    height: u32,
+    channels: Vec<ChannelStats>,
+}
+
+/// What one job did: the files it read and wrote, what it warned about and
+/// how it failed
+#[derive(Debug, Default)]
+struct Record {
+    item: String,
+    inputs: Vec<PathBuf>,
+    outputs: Vec<Output>,
+    warnings: Vec<String>,
+    /// Problems `lint` found, with their severity
+    issues: Vec<(&'static str, String)>,
+    error: Option<(Kind, String)>,
+}
+
+thread_local! {
+    /// The records of the jobs running on this thread, innermost last
+    ///
+    /// A job waiting on other threads can run another job on this one, which
+    /// finishes before the first job carries on, so the innermost record is
+    /// always the one to add to.
+    static CURRENT: RefCell<Vec<Record>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The records of every job which has finished, with the position of the
+/// job's item
+static FINISHED: Mutex<Vec<(usize, Record)>> = Mutex::new(Vec::new());
+
+fn with_current(update: impl FnOnce(&mut Record)) {
+    CURRENT.with(|current| {
+        if let Some(record) = current.borrow_mut().last_mut() {
+            update(record);
+        }
+    });
+}
+
+/// Runs a job for one item, recording what it does
+///
+/// Reports list records in the order of `position`, whatever order the jobs
+/// finish in.
+pub fn record(position: usize, item: String, job: impl FnOnce() -> Result<()>) -> Result<()> {
+    CURRENT.with(|current| {
+        current.borrow_mut().push(Record {
+            item,
+            ..Record::default()
+        })
+    });
+
+    let outcome = job();
+
+    let mut record = CURRENT
+        .with(|current| current.borrow_mut().pop())
+        .unwrap_or_default();
+
+    if let Err(error) = &outcome {
+        record.error = Some((kind(error), format!("{:#}", error)));
+    }
+
+    FINISHED
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push((position, record));
+
+    outcome
+}
+
+/// Notes that the current job read an input file
+pub fn input(path: &Path) {
+    with_current(|record| record.inputs.push(path.to_path_buf()));
+}
+
+/// Notes a problem `lint` found with the current job's file
+pub fn issue(severity: &'static str, message: &str) {
+    with_current(|record| record.issues.push((severity, message.to_string())));
+}
+
+/// Notes that the current job wrote an output file holding `texture`
+pub fn output(path: &Path, texture: Option<Texture>, image: &DynamicImage) {
+    if !is_json() {
+        return;
+    }
+
+    // Images are read as RGBA, where grey is in red and alpha stays alpha
+    let indices: &[usize] = match image.color().channel_count() {
+        1 => &[0],
+        2 => &[0, 3],
+        3 => &[0, 1, 2],
+        _ => &[0, 1, 2, 3],
+    };
+
+    let pixels = image.to_rgba32f();
+    let count = (pixels.width() * pixels.height()).max(1) as f32;
+
+    let channels = indices
+        .iter()
+        .map(|&channel| {
+            let mut stats = ChannelStats {
+                min: f32::INFINITY,
+                max: f32::NEG_INFINITY,
+                mean: 0.0,
+            };
+
+            for pixel in pixels.pixels() {
+                stats.min = stats.min.min(pixel[channel]);
+                stats.max = stats.max.max(pixel[channel]);
+                stats.mean += pixel[channel] / count;
+            }
+
+            stats
+        })
+        .collect();
+
+    let kind = texture.map(|texture| match texture {
+        Texture::Map(map) => map.name(),
+        Texture::Packed(layout) => layout.name,
+    });
+
+    with_current(|record| {
+        record.outputs.push(Output {
+            path: path.to_path_buf(),
+            kind,
+            width: image.width(),
+            height: image.height(),
+            channels,
+        })
+    });
+}
+
+/// Passes log messages on to env_logger, keeping warnings for the record of
+/// the job which logged them
+struct Logger {
+    inner: env_logger::Logger,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if record.level() == Level::Warn {
+            with_current(|current| current.warnings.push(record.args().to_string()));
+        }
+
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Sets up logging, configured by `RUST_LOG` as env_logger is, but always
+/// keeping warnings for reports
+///
+/// Warnings are shown unless `RUST_LOG` says otherwise.
+pub fn init_logger() {
+    let inner =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).build();
+    let level = inner.filter().max(LevelFilter::Warn);
+
+    if log::set_boxed_logger(Box::new(Logger { inner })).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Writes a string as a JSON string
+fn json_string(json: &mut String, value: &str) {
+    json.push('"');
+
+    for character in value.chars() {
+        match character {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            character if character.is_control() => {
+                let _ = write!(json, "\\u{:04x}", character as u32);
+            }
+            character => json.push(character),
+        }
+    }
+
+    json.push('"');
+}
+
+/// Writes a number as a JSON number, or null if JSON cannot hold it
+fn json_number(json: &mut String, value: f32) {
+    if value.is_finite() {
+        let _ = write!(json, "{}", value);
+    } else {
+        json.push_str("null");
+    }
+}
+
+fn json_error(json: &mut String, error: Option<(Kind, &str)>) {
+    match error {
+        Some((kind, message)) => {
+            json.push_str("{\"kind\":");
+            json_string(json, kind.name());
+            json.push_str(",\"message\":");
+            json_string(json, message);
+            json.push('}');
+        }
+        None => json.push_str("null"),
+    }
+}
+
+fn json_list<T>(json: &mut String, items: &[T], mut item: impl FnMut(&mut String, &T)) {
+    json.push('[');
+
+    for (index, value) in items.iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+
+        item(json, value);
+    }
+
+    json.push(']');
+}
+
+fn json_path(json: &mut String, path: &Path) {
+    json_string(json, &path.to_string_lossy());
+}
+
+fn json_record(json: &mut String, record: &&Record) {
+    json.push_str("{\"item\":");
+    json_string(json, &record.item);
+    json.push_str(",\"inputs\":");
+    json_list(json, &record.inputs, |json, path| json_path(json, path));
+    json.push_str(",\"outputs\":");
+    json_list(json, &record.outputs, |json, output| {
+        json.push_str("{\"path\":");
+        json_path(json, &output.path);
+        json.push_str(",\"kind\":");
+
+        match output.kind {
+            Some(kind) => json_string(json, kind),
+            None => json.push_str("null"),
+        }
+
+        let _ = write!(
+            json,
+            ",\"width\":{},\"height\":{},\"channels\":",
+            output.width, output.height
+        );
+
+        json_list(json, &output.channels, |json, stats| {
+            json.push_str("{\"min\":");
+            json_number(json, stats.min);
+            json.push_str(",\"max\":");
+            json_number(json, stats.max);
+            json.push_str(",\"mean\":");
+            json_number(json, stats.mean);
+            json.push('}');
+        });
+
+        json.push('}');
+    });
+    json.push_str(",\"warnings\":");
+    json_list(json, &record.warnings, |json, warning| {
+        json_string(json, warning)
+    });
+    json.push_str(",\"issues\":");
+    json_list(json, &record.issues, |json, (severity, message)| {
+        json.push_str("{\"severity\":");
+        json_string(json, severity);
+        json.push_str(",\"message\":");
+        json_string(json, message);
+        json.push('}');
+    });
+    json.push_str(",\"error\":");
+    json_error(
+        json,
+        record
+            .error
+            .as_ref()
+            .map(|(kind, message)| (*kind, message.as_str())),
+    );
+    json.push('}');
+}
+
+/// Prints the JSON report of every job, if what was done is reported as
+/// JSON, along with how the whole command went
+pub fn finish(outcome: &Result<()>) {
+    if !is_json() {
+        return;
+    }
+
+    let mut finished = FINISHED
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    finished.sort_by_key(|(position, _)| *position);
+
+    let records: Vec<&Record> = finished.iter().map(|(_, record)| record).collect();
+
+    let failed = records
+        .iter()
+        .filter(|record| record.error.is_some())
+        .count();
+
+    let mut json = String::from("{\"records\":");
+    json_list(&mut json, &records, json_record);
+
+    let _ = write!(
+        json,
+        ",\"succeeded\":{},\"failed\":{},\"error\":",
+        records.len() - failed,
+        failed
+    );
+
+    let message = outcome.as_ref().err().map(|error| format!("{:#}", error));
+
+    json_error(
+        &mut json,
+        outcome.as_ref().err().map(kind).zip(message.as_deref()),
+    );
+
+    let _ = write!(json, ",\"exit_code\":{}}}", exit_code(outcome));
+
+    println!("{}", json);
+}
+
+/// The exit code of a command which ended with `outcome`
+pub fn exit_code(outcome: &Result<()>) -> u8 {
+    match outcome {
+        Ok(()) => 0,
+        Err(error) => kind(error).exit_code(),
+    }
+}
@@ -0,0 +1,385 @@
+use crate::color::{self, ColorSpace};
+use crate::depth;
+use crate::layout::{Map, MAPS};
+use crate::texture_set::Texture;
+use anyhow::{anyhow, bail, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, Rgba, Rgba32FImage};
+use std::fmt;
+use std::str::FromStr;
+
+/// How pixels are blended when resizing an image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// The closest pixel, keeping hard edges and exact values
+    Nearest,
+    /// A blend of the closest pixels
+    #[default]
+    Bilinear,
+    /// A sharp blend of many nearby pixels, which can ring around edges
+    Lanczos,
+    /// The average of every pixel covered, for shrinking images without
+    /// aliasing
+    Box,
+}
+
+/// The command line names of every resize filter
+pub const NAMES: &[&str] = &["nearest", "bilinear", "lanczos", "box"];
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Filter::Nearest),
+            "bilinear" => Ok(Filter::Bilinear),
+            "lanczos" => Ok(Filter::Lanczos),
+            "box" => Ok(Filter::Box),
+            _ => bail!(
+                "Unknown resize filter {:?}, expected one of {}",
+                name,
+                NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            Filter::Nearest => "nearest",
+            Filter::Bilinear => "bilinear",
+            Filter::Lanczos => "Lanczos",
+            Filter::Box => "box",
+        })
+    }
+}
+
+/// The part of each source pixel which goes into each target pixel, along
+/// one axis, for a box filter
+fn box_weights(source: u32, target: u32) -> Vec<Vec<(u32, f32)>> {
+    let scale = source as f32 / target as f32;
+
+    (0..target)
+        .map(|position| {
+            let start = position as f32 * scale;
+            let end = start + scale;
+
+            (start.floor() as u32..(end.ceil() as u32).min(source))
+                .filter_map(|source_position| {
+                    let covered =
+                        end.min(source_position as f32 + 1.0) - start.max(source_position as f32);
+
+                    (covered > 0.0).then_some((source_position, covered / scale))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Resizes an image by averaging the pixels each target pixel covers,
+/// first across and then down
+pub(crate) fn box_resize(image: &Rgba32FImage, width: u32, height: u32) -> Rgba32FImage {
+    let columns = box_weights(image.width(), width);
+    let rows = box_weights(image.height(), height);
+
+    let across = Rgba32FImage::from_fn(width, image.height(), |x_position, y_position| {
+        let mut sum = [0.0; 4];
+
+        for &(source, weight) in &columns[x_position as usize] {
+            for (total, value) in sum.iter_mut().zip(image.get_pixel(source, y_position).0) {
+                *total += value * weight;
+            }
+        }
+
+        Rgba(sum)
+    });
+
+    Rgba32FImage::from_fn(width, height, |x_position, y_position| {
+        let mut sum = [0.0; 4];
+
+        for &(source, weight) in &rows[y_position as usize] {
+            for (total, value) in sum.iter_mut().zip(across.get_pixel(x_position, source).0) {
+                *total += value * weight;
+            }
+        }
+
+        Rgba(sum)
+    })
+}
+
+/// Resizes the pixels of an image
+fn resize_pixels(pixels: &Rgba32FImage, width: u32, height: u32, filter: Filter) -> Rgba32FImage {
+    match filter {
+        Filter::Nearest => imageops::resize(pixels, width, height, FilterType::Nearest),
+        Filter::Bilinear => imageops::resize(pixels, width, height, FilterType::Triangle),
+        Filter::Lanczos => imageops::resize(pixels, width, height, FilterType::Lanczos3),
+        Filter::Box => box_resize(pixels, width, height),
+    }
+}
+
+/// Resizes an image, keeping it the same kind of image
+pub fn resize(image: &DynamicImage, width: u32, height: u32, filter: Filter) -> DynamicImage {
+    if (image.width(), image.height()) == (width, height) {
+        return image.clone();
+    }
+
+    let resized = resize_pixels(&image.to_rgba32f(), width, height, filter);
+
+    depth::convert(DynamicImage::ImageRgba32F(resized), image.color())
+}
+
+/// The filter which suits resizing what a texture holds
+///
+/// Colour maps are kept sharp with Lanczos filtering. Everything else,
+/// such as roughness, metallic, packed textures and normal maps, is
+/// averaged with a box filter, which never makes values outside the range
+/// of the pixels it blends.
+pub fn filter_for(texture: Option<Texture>) -> Filter {
+    match texture {
+        Some(Texture::Map(Map::Normal)) => Filter::Box,
+        Some(Texture::Map(map)) if map.color_space() == ColorSpace::Srgb => Filter::Lanczos,
+        _ => Filter::Box,
+    }
+}
+
+/// Resizes a texture with the filter which suits what it holds
+///
+/// The colour channels of sRGB images are blended as linear light, and
+/// data such as roughness is blended as the values it is stored as. The
+/// normals of normal maps are made unit length again afterwards, as
+/// blending them makes them shorter.
+pub fn resize_texture(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    texture: Option<Texture>,
+    color_space: Option<ColorSpace>,
+) -> DynamicImage {
+    if (image.width(), image.height()) == (width, height) {
+        return image.clone();
+    }
+
+    let filter = filter_for(texture);
+    let normal = texture == Some(Texture::Map(Map::Normal));
+    let srgb = color_space == Some(ColorSpace::Srgb) && !normal;
+
+    let mut pixels = image.to_rgba32f();
+
+    if srgb {
+        for pixel in pixels.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                *channel = color::srgb_to_linear(*channel);
+            }
+        }
+    }
+
+    let mut resized = resize_pixels(&pixels, width, height, filter);
+
+    for pixel in resized.pixels_mut() {
+        if normal {
+            let [x, y, z] = [0, 1, 2].map(|channel| pixel[channel] * 2.0 - 1.0);
+            let length = (x * x + y * y + z * z).sqrt();
+
+            let unit = if length > f32::EPSILON {
+                [x / length, y / length, z / length]
+            } else {
+                [0.0, 0.0, 1.0]
+            };
+
+            for (channel, value) in pixel.0.iter_mut().zip(unit) {
+                *channel = value * 0.5 + 0.5;
+            }
+        } else if filter == Filter::Lanczos {
+            // Lanczos filtering rings around edges, which must not make
+            // negative colours in float images
+            for channel in &mut pixel.0 {
+                *channel = channel.max(0.0);
+            }
+        }
+
+        if srgb {
+            for channel in &mut pixel.0[..3] {
+                *channel = color::linear_to_srgb(*channel);
+            }
+        }
+    }
+
+    depth::convert(DynamicImage::ImageRgba32F(resized), image.color())
+}
+
+/// Which way to round sizes to powers of two
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerOfTwo {
+    Up,
+    Down,
+    Nearest,
+}
+
+/// The command line names of every way of rounding to powers of two
+pub const POWER_OF_TWO_NAMES: &[&str] = &["up", "down", "nearest"];
+
+impl FromStr for PowerOfTwo {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "up" => Ok(PowerOfTwo::Up),
+            "down" => Ok(PowerOfTwo::Down),
+            "nearest" => Ok(PowerOfTwo::Nearest),
+            _ => bail!(
+                "Unknown way to round to powers of two {:?}, expected one of {}",
+                name,
+                POWER_OF_TWO_NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+impl PowerOfTwo {
+    /// Rounds a width or height to a power of two, rounding down instead
+    /// if rounding any other way would make it larger than `limit`
+    pub fn round(self, size: u32, limit: Option<u32>) -> u32 {
+        let down = 1 << (u32::BITS - 1 - size.max(1).leading_zeros());
+        let up = if down == size {
+            down
+        } else {
+            down.checked_mul(2).unwrap_or(down)
+        };
+
+        let rounded = match self {
+            PowerOfTwo::Up => up,
+            PowerOfTwo::Down => down,
+            PowerOfTwo::Nearest if size - down < up - size => down,
+            PowerOfTwo::Nearest => up,
+        };
+
+        match limit {
+            Some(limit) if rounded > limit => down,
+            _ => rounded,
+        }
+    }
+}
+
+/// The size to resize maps of different sizes to, so that they can be
+/// packed together
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// The size of the map with the most pixels
+    Larger,
+    /// The size of the map with the fewest pixels
+    Smaller,
+    /// The size of one of the maps
+    Map(Map),
+    /// A width and height
+    Size(u32, u32),
+}
+
+impl FromStr for Target {
+    type Err = anyhow::Error;
+
+    fn from_str(target: &str) -> Result<Self> {
+        let lowercase = target.to_ascii_lowercase();
+
+        match lowercase.as_str() {
+            "larger" => return Ok(Target::Larger),
+            "smaller" => return Ok(Target::Smaller),
+            _ => {}
+        }
+
+        if let Some(map) = MAPS.iter().find(|map| map.name() == lowercase) {
+            return Ok(Target::Map(*map));
+        }
+
+        let invalid = || {
+            anyhow!(
+                "Invalid size to resize to {:?}, expected larger, smaller, a map such as metallic or roughness, or <width>x<height>",
+                target
+            )
+        };
+
+        let (width, height) = lowercase.split_once('x').ok_or_else(invalid)?;
+        let width: u32 = width.parse().map_err(|_| invalid())?;
+        let height: u32 = height.parse().map_err(|_| invalid())?;
+
+        if width == 0 || height == 0 {
+            bail!("Cannot resize to {}x{}, which has no pixels", width, height);
+        }
+
+        Ok(Target::Size(width, height))
+    }
+}
+
+impl Target {
+    /// Works out the size to resize to, from the size of each map
+    pub fn size(self, sizes: &[(Map, (u32, u32))]) -> Result<(u32, u32)> {
+        let area = |(_, (width, height)): &&(Map, (u32, u32))| *width as u64 * *height as u64;
+
+        let size = match self {
+            Target::Larger => sizes.iter().max_by_key(area),
+            Target::Smaller => sizes.iter().min_by_key(area),
+            Target::Map(map) => match sizes.iter().find(|(sized_map, _)| *sized_map == map) {
+                Some(size) => Some(size),
+                None => bail!("There is no {} file to take the size from!", map),
+            },
+            Target::Size(width, height) => return Ok((width, height)),
+        };
+
+        size.map(|(_, size)| *size)
+            .ok_or_else(|| anyhow!("There are no images to take the size from!"))
+    }
+}
+
+/// Resizes the images of separate maps to the same size, warning about each
+/// one resized, or fails if they are different sizes without a target
+///
+/// `images` holds an image, if there is one, for each of `maps`.
+pub fn same_size(
+    images: Vec<Option<DynamicImage>>,
+    maps: &[Map],
+    target: Option<Target>,
+    filter: Filter,
+) -> Result<Vec<Option<DynamicImage>>> {
+    let sizes: Vec<(Map, (u32, u32))> = maps
+        .iter()
+        .zip(&images)
+        .filter_map(|(map, image)| Some((*map, image.as_ref()?.dimensions())))
+        .collect();
+
+    let (width, height) = match target {
+        Some(target) => target.size(&sizes)?,
+        None if sizes.windows(2).any(|pair| pair[0].1 != pair[1].1) => bail!(
+            "Input images are not the same size! ({}) Use --resize-to to resize them",
+            sizes
+                .iter()
+                .map(|(map, (width, height))| format!("{} is {}x{}", map, width, height))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        None => return Ok(images),
+    };
+
+    Ok(maps
+        .iter()
+        .zip(images)
+        .map(|(map, image)| {
+            let image = image?;
+
+            if image.dimensions() == (width, height) {
+                return Some(image);
+            }
+
+            warn!(
+                "Resizing the {} map from {}x{} to {}x{} with {} filtering",
+                map,
+                image.width(),
+                image.height(),
+                width,
+                height,
+                filter
+            );
+
+            Some(resize(&image, width, height, filter))
+        })
+        .collect())
+}
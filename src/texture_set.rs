@@ -0,0 +1,479 @@
+use crate::color::{self, ColorSpace};
+use crate::curve::RoughnessCurve;
+use crate::image_file;
+use crate::layout::{self, Layout, Map, MAPS};
+use crate::normal::{self, NormalConvention, NormalOptions};
+use anyhow::{anyhow, bail, Result};
+use image::DynamicImage;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What a texture file holds
+#[derive(Clone, Copy, Debug)]
+pub enum Texture {
+    /// A single map
+    Map(Map),
+    /// Several maps packed together
+    Packed(&'static Layout),
+}
+
+impl PartialEq for Texture {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Texture::Map(map), Texture::Map(other_map)) => map == other_map,
+            (Texture::Packed(layout), Texture::Packed(other_layout)) => {
+                layout.name == other_layout.name
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Texture {
+    /// The colour space the texture is usually stored in, which is linear
+    /// for packed maps
+    pub fn color_space(&self) -> ColorSpace {
+        match self {
+            Texture::Map(map) => map.color_space(),
+            Texture::Packed(_) => ColorSpace::Linear,
+        }
+    }
+}
+
+/// A texture file which belongs to a texture set
+#[derive(Debug)]
+pub struct TextureFile {
+    pub path: PathBuf,
+    pub texture: Texture,
+    /// The convention of a normal map, if its name says which it uses
+    pub normal: Option<NormalConvention>,
+}
+
+/// Every texture file for one material
+#[derive(Debug)]
+pub struct TextureSet {
+    /// The name shared by every texture file, without what each one holds
+    pub name: String,
+    /// The name as written in the first texture file, keeping any
+    /// separator before what it holds, such as the `_` of `Rusty_Metallic`
+    pub stem: String,
+    /// The directory holding the texture files
+    pub directory: PathBuf,
+    pub files: Vec<TextureFile>,
+}
+
+/// A texture which was converted, ready to be written
+pub struct ConvertedTexture {
+    /// The prefix of the texture's file name, before the set's name
+    pub prefix: &'static str,
+    /// The suffix of the texture's file name, after the set's name
+    pub suffix: &'static str,
+    /// A short description of what the texture holds
+    pub description: &'static str,
+    /// What the texture holds
+    pub texture: Texture,
+    pub image: DynamicImage,
+    /// The normal map of the set, for packed textures, for widening
+    /// roughness in their mip levels
+    pub normal: Option<DynamicImage>,
+}
+
+/// How textures for a material are named, packed and laid out for an
+/// engine or file format
+#[derive(Debug)]
+pub struct Convention {
+    /// The name of the convention on the command line
+    pub name: &'static str,
+    /// How metallic, roughness and related maps are packed, if they are
+    pub layout: Option<&'static Layout>,
+    /// Which way the green channel of normal maps points
+    pub normal: NormalConvention,
+    /// The prefix of file names holding separate maps
+    pub prefix: &'static str,
+    /// Suffixes of file names holding separate maps, for maps which are not
+    /// named with [`Map::suffix`]
+    pub suffixes: &'static [(Map, &'static str)],
+}
+
+/// Pixar USD-style separate files for every map
+pub const USD: Convention = Convention {
+    name: "usd",
+    layout: None,
+    normal: NormalConvention::OpenGl,
+    prefix: "",
+    suffixes: &[],
+};
+
+/// Unity Standard shader textures, with metallic and smoothness packed
+pub const UNITY: Convention = Convention {
+    name: "unity",
+    layout: Some(&layout::UNITY),
+    normal: NormalConvention::OpenGl,
+    prefix: "",
+    suffixes: &[],
+};
+
+/// Unity High Definition Render Pipeline textures, with a mask map
+pub const HDRP: Convention = Convention {
+    name: "hdrp",
+    layout: Some(&layout::HDRP),
+    normal: NormalConvention::OpenGl,
+    prefix: "",
+    suffixes: &[],
+};
+
+/// glTF 2.0 textures, with occlusion, roughness and metallic packed
+pub const GLTF: Convention = Convention {
+    name: "gltf",
+    layout: Some(&layout::GLTF_ORM),
+    normal: NormalConvention::OpenGl,
+    prefix: "",
+    suffixes: &[],
+};
+
+/// Unreal Engine textures, with occlusion, roughness and metallic packed,
+/// and named like `T_Name_D`
+pub const UNREAL: Convention = Convention {
+    name: "unreal",
+    layout: Some(&layout::UNREAL_ORM),
+    normal: NormalConvention::DirectX,
+    prefix: "T_",
+    suffixes: &[
+        (Map::BaseColor, "_D"),
+        (Map::Normal, "_N"),
+        (Map::Emission, "_E"),
+        (Map::Height, "_H"),
+        (Map::Metallic, "_M"),
+        (Map::Roughness, "_R"),
+        (Map::Occlusion, "_AO"),
+        (Map::DetailMask, "_DM"),
+    ],
+};
+
+/// Every known convention
+pub const CONVENTIONS: &[&Convention] = &[&USD, &UNITY, &HDRP, &GLTF, &UNREAL];
+
+/// The command line names of every known convention
+pub const NAMES: &[&str] = &[USD.name, UNITY.name, HDRP.name, GLTF.name, UNREAL.name];
+
+/// Other names texture files commonly use for maps
+const ALIASES: &[(Map, &str)] = &[
+    (Map::BaseColor, "Albedo"),
+    (Map::BaseColor, "Base_Color"),
+    (Map::BaseColor, "Color"),
+    (Map::Metallic, "Metalness"),
+    (Map::Occlusion, "AO"),
+    (Map::Occlusion, "AmbientOcclusion"),
+    (Map::Occlusion, "Ambient_Occlusion"),
+    (Map::Height, "Displacement"),
+    (Map::Emission, "Emissive"),
+    (Map::SpecularGlossiness, "Specular"),
+];
+
+/// Suffixes of normal maps which say which convention they use
+const NORMAL_SUFFIXES: &[(NormalConvention, &str)] = &[
+    (NormalConvention::OpenGl, "Normal_OpenGL"),
+    (NormalConvention::DirectX, "Normal_DirectX"),
+];
+
+/// Finds a convention by its command line name
+pub fn find(name: &str) -> Result<&'static Convention> {
+    match CONVENTIONS
+        .iter()
+        .find(|convention| convention.name == name)
+    {
+        Some(convention) => Ok(convention),
+        None => bail!(
+            "Unknown convention {:?}, expected one of {}",
+            name,
+            NAMES.join(", ")
+        ),
+    }
+}
+
+impl Convention {
+    /// The suffix of file names holding a separate map
+    pub fn suffix(&self, map: Map) -> &'static str {
+        self.suffixes
+            .iter()
+            .find(|(suffix_map, _)| *suffix_map == map)
+            .map(|(_, suffix)| *suffix)
+            .unwrap_or_else(|| map.suffix())
+    }
+}
+
+/// Strips a suffix from the end of a file stem, ignoring case
+fn strip_suffix_ignoring_case<'a>(stem: &'a str, suffix: &str) -> Option<&'a str> {
+    let split = stem.len().checked_sub(suffix.len())?;
+
+    if stem.is_char_boundary(split) && stem[split..].eq_ignore_ascii_case(suffix) {
+        Some(&stem[..split])
+    } else {
+        None
+    }
+}
+
+/// A suffix which input files holding a map or packed texture use instead
+/// of the usual one, given on the command line as `<kind>=<suffix>`
+#[derive(Clone, Debug)]
+pub struct InputSuffix {
+    pub texture: Texture,
+    pub suffix: String,
+}
+
+impl FromStr for InputSuffix {
+    type Err = anyhow::Error;
+
+    fn from_str(input_suffix: &str) -> Result<Self> {
+        let (kind, suffix) = input_suffix.split_once('=').ok_or_else(|| {
+            anyhow!(
+                "Invalid input suffix {:?}, expected <kind>=<suffix>",
+                input_suffix
+            )
+        })?;
+
+        if suffix.is_empty() {
+            bail!("Input suffix for {:?} is empty", kind);
+        }
+
+        let texture = match MAPS.iter().find(|map| map.name() == kind) {
+            Some(map) => Texture::Map(*map),
+            None => Texture::Packed(layout::find(kind).map_err(|_| {
+                anyhow!(
+                    "Unknown kind of texture {:?}, expected a map such as {} or a layout such as {}",
+                    kind,
+                    Map::Roughness.name(),
+                    layout::UNITY.name
+                )
+            })?),
+        };
+
+        Ok(InputSuffix {
+            texture,
+            suffix: suffix.to_string(),
+        })
+    }
+}
+
+/// Works out what a texture file holds and the name of its texture set,
+/// from the suffix of its name
+///
+/// When several suffixes match, the longest one wins, so that
+/// `RustyMetallicSmoothness` is a packed texture rather than a smoothness
+/// map. Suffixes in `input_suffixes` win over built-in ones of the same
+/// length.
+pub fn recognise(
+    path: &Path,
+    input_suffixes: &[InputSuffix],
+) -> Option<(String, Texture, Option<NormalConvention>)> {
+    let (stem, texture, normal) = recognise_stem(path, input_suffixes)?;
+
+    Some((set_name(&stem)?.to_string(), texture, normal))
+}
+
+/// The name of a texture set, from the name of one of its files without
+/// what it holds, ignoring separators so that `Rusty_Metallic` and
+/// `RustyRoughness` are in the same set
+fn set_name(stem: &str) -> Option<&str> {
+    let name = stem.trim_end_matches(['_', '-', ' ', '.']);
+
+    (!name.is_empty()).then_some(name)
+}
+
+/// Works out what a texture file holds, and its name without the suffix
+/// saying so, keeping any separator before the suffix such as the `_` of
+/// `Rusty_Metallic`
+fn recognise_stem(
+    path: &Path,
+    input_suffixes: &[InputSuffix],
+) -> Option<(String, Texture, Option<NormalConvention>)> {
+    let stem = image_file::stem(path).ok()?;
+
+    let mut candidates: Vec<(&str, &str, Texture, Option<NormalConvention>)> = Vec::new();
+
+    for layout in layout::LAYOUTS {
+        candidates.push((layout.prefix, layout.suffix, Texture::Packed(layout), None));
+    }
+
+    for map in MAPS {
+        candidates.push(("", map.suffix(), Texture::Map(*map), None));
+    }
+
+    for (map, alias) in ALIASES {
+        candidates.push(("", alias, Texture::Map(*map), None));
+    }
+
+    for convention in CONVENTIONS {
+        for (map, suffix) in convention.suffixes {
+            candidates.push((convention.prefix, suffix, Texture::Map(*map), None));
+        }
+    }
+
+    for (convention, suffix) in NORMAL_SUFFIXES {
+        candidates.push(("", suffix, Texture::Map(Map::Normal), Some(*convention)));
+    }
+
+    for input_suffix in input_suffixes {
+        candidates.push(("", &input_suffix.suffix, input_suffix.texture, None));
+    }
+
+    let (prefix, suffix, texture, normal) = candidates
+        .into_iter()
+        .filter(|(prefix, suffix, _, _)| {
+            stem.starts_with(prefix) && strip_suffix_ignoring_case(&stem, suffix).is_some()
+        })
+        .max_by_key(|(prefix, suffix, _, _)| prefix.len() + suffix.len())?;
+
+    let name = strip_suffix_ignoring_case(&stem, suffix)?;
+    let name = name.strip_prefix(prefix).unwrap_or(name);
+
+    Some((name.to_string(), texture, normal))
+}
+
+/// Groups texture files into texture sets, by the directory they are in
+/// and their name
+///
+/// Files whose names do not say what they hold are skipped.
+pub fn group(paths: &[PathBuf], input_suffixes: &[InputSuffix]) -> Vec<TextureSet> {
+    let mut sets: BTreeMap<(PathBuf, String), TextureSet> = BTreeMap::new();
+
+    for path in paths {
+        let recognised =
+            recognise_stem(path, input_suffixes).and_then(|(stem, texture, normal)| {
+                Some((set_name(&stem)?.to_string(), stem, texture, normal))
+            });
+
+        let (name, stem, texture, normal) = match recognised {
+            Some(recognised) => recognised,
+            None => {
+                debug!("Skipping {:?}, which is not a known kind of texture", path);
+                continue;
+            }
+        };
+
+        let directory = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+
+        sets.entry((directory.clone(), name.clone()))
+            .or_insert_with(|| TextureSet {
+                name,
+                stem,
+                directory,
+                files: Vec::new(),
+            })
+            .files
+            .push(TextureFile {
+                path: path.clone(),
+                texture,
+                normal,
+            });
+    }
+
+    sets.into_values().collect()
+}
+
+impl TextureSet {
+    /// Converts every texture in the set from one convention to another,
+    /// unpacking, repacking, renaming and converting normal maps as needed
+    ///
+    /// Every map is read in its usual colour space, converting it if the
+    /// file is stored in another one, or in `input_colorspace` if it is
+    /// given.
+    pub fn convert(
+        &self,
+        from: &Convention,
+        to: &Convention,
+        curve: RoughnessCurve,
+        input_colorspace: Option<ColorSpace>,
+    ) -> Result<Vec<ConvertedTexture>> {
+        let mut maps: BTreeMap<Map, (DynamicImage, NormalConvention)> = BTreeMap::new();
+
+        // Separate maps come first, so that they win over the same map
+        // unpacked from a packed texture
+        for file in &self.files {
+            if let Texture::Map(map) = file.texture {
+                if maps.contains_key(&map) {
+                    warn!("{:?} is a second {} map, ignoring it", file.path, map);
+                    continue;
+                }
+
+                let normal = file.normal.unwrap_or(from.normal);
+
+                let image = color::open(&file.path, map.color_space(), input_colorspace)?;
+
+                maps.insert(map, (image, normal));
+            }
+        }
+
+        for file in &self.files {
+            if let Texture::Packed(layout) = file.texture {
+                let image = color::open(&file.path, ColorSpace::Linear, input_colorspace)?;
+
+                for (map, image) in layout.split(image, curve)? {
+                    if maps.contains_key(&map) {
+                        warn!(
+                            "{:?} has a {} map which is already in a separate file, ignoring it",
+                            file.path, map
+                        );
+                        continue;
+                    }
+
+                    maps.insert(map, (image, from.normal));
+                }
+            }
+        }
+
+        convert_maps(maps, to, curve)
+    }
+}
+
+/// Converts separate maps to the textures of a convention, packing them and
+/// converting normal maps as needed
+///
+/// Each map comes with the convention its normal map uses, which only
+/// matters for normal maps.
+pub fn convert_maps(
+    mut maps: BTreeMap<Map, (DynamicImage, NormalConvention)>,
+    to: &Convention,
+    curve: RoughnessCurve,
+) -> Result<Vec<ConvertedTexture>> {
+    let mut converted = Vec::new();
+
+    if let Some(layout) = to.layout {
+        if layout.maps.iter().any(|map| maps.contains_key(map)) {
+            let images = layout
+                .maps
+                .iter()
+                .map(|map| maps.remove(map).map(|(image, _)| image))
+                .collect();
+
+            converted.push(ConvertedTexture {
+                prefix: layout.prefix,
+                suffix: layout.suffix,
+                description: layout.description,
+                texture: Texture::Packed(layout),
+                image: layout.merge(images, curve)?,
+                normal: maps.get(&Map::Normal).map(|(image, _)| image.clone()),
+            });
+        }
+    }
+
+    for (map, (image, normal)) in maps {
+        let image = if map == Map::Normal && normal != to.normal {
+            normal::convert(&image, normal, to.normal, NormalOptions::default())
+        } else {
+            image
+        };
+
+        converted.push(ConvertedTexture {
+            prefix: to.prefix,
+            suffix: to.suffix(map),
+            description: map.description(),
+            texture: Texture::Map(map),
+            image,
+            normal: None,
+        });
+    }
+
+    Ok(converted)
+}
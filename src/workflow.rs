@@ -0,0 +1,263 @@
+use crate::color;
+use crate::depth::{self, BitDepth};
+use anyhow::{bail, Result};
+use image::{DynamicImage, GenericImageView, Rgba, Rgba32FImage};
+
+/// How much light dielectric (non-metallic) surfaces reflect head-on
+const DIELECTRIC_SPECULAR: f32 = 0.04;
+
+/// Smallest value divided by, to avoid dividing by zero
+const EPSILON: f32 = 1e-6;
+
+/// Textures for the specular and glossiness workflow
+pub struct SpecularGlossiness {
+    /// Diffuse colour, with opacity in alpha
+    pub diffuse: DynamicImage,
+    /// Specular colour, with glossiness in alpha
+    pub specular_glossiness: DynamicImage,
+}
+
+/// Textures for the metallic and roughness workflow
+pub struct MetallicRoughness {
+    /// Base colour, with opacity in alpha
+    pub base_color: DynamicImage,
+    pub metallic: DynamicImage,
+    pub roughness: DynamicImage,
+}
+
+fn unit(value: f32) -> f32 {
+    value.clamp(0.0, 1.0)
+}
+
+fn linear_colour(pixel: &Rgba<f32>) -> [f32; 3] {
+    [
+        color::srgb_to_linear(unit(pixel[0])),
+        color::srgb_to_linear(unit(pixel[1])),
+        color::srgb_to_linear(unit(pixel[2])),
+    ]
+}
+
+fn srgb_pixel(colour: [f32; 3], alpha: f32) -> Rgba<f32> {
+    Rgba([
+        unit(color::linear_to_srgb(colour[0])),
+        unit(color::linear_to_srgb(colour[1])),
+        unit(color::linear_to_srgb(colour[2])),
+        unit(alpha),
+    ])
+}
+
+fn grey_pixel(value: f32) -> Rgba<f32> {
+    let value = unit(value);
+
+    Rgba([value, value, value, 1.0])
+}
+
+/// Converts a worked out image to the most precise depth of the input
+/// images, with `channels` channels
+fn to_depth(image: Rgba32FImage, depth: BitDepth, channels: usize) -> DynamicImage {
+    depth::convert(
+        DynamicImage::ImageRgba32F(image),
+        depth.color_type(channels),
+    )
+}
+
+/// The number of colour channels, with or without alpha, of an image
+/// worked out from a colour image
+fn colour_channels(image: &DynamicImage) -> usize {
+    if has_alpha(image) {
+        4
+    } else {
+        3
+    }
+}
+
+fn perceived_brightness(colour: [f32; 3]) -> f32 {
+    (0.299 * colour[0] * colour[0] + 0.587 * colour[1] * colour[1] + 0.114 * colour[2] * colour[2])
+        .sqrt()
+}
+
+fn max_component(colour: [f32; 3]) -> f32 {
+    colour[0].max(colour[1]).max(colour[2])
+}
+
+/// Finds the metalness which reflects the same amount of light as a
+/// diffuse and specular brightness, by solving the quadratic relating them
+fn solve_metallic(diffuse: f32, specular: f32, one_minus_specular_strength: f32) -> f32 {
+    if specular < DIELECTRIC_SPECULAR {
+        return 0.0;
+    }
+
+    let a = DIELECTRIC_SPECULAR;
+    let b = diffuse * one_minus_specular_strength / (1.0 - DIELECTRIC_SPECULAR) + specular
+        - 2.0 * DIELECTRIC_SPECULAR;
+    let c = DIELECTRIC_SPECULAR - specular;
+    let discriminant = (b * b - 4.0 * a * c).max(0.0);
+
+    ((-b + discriminant.sqrt()) / (2.0 * a)).clamp(0.0, 1.0)
+}
+
+fn has_alpha(image: &DynamicImage) -> bool {
+    image.color().has_alpha()
+}
+
+/// The most precise depth of any of the input images
+fn deepest(images: &[&DynamicImage]) -> BitDepth {
+    images
+        .iter()
+        .map(|image| BitDepth::of(image))
+        .max()
+        .unwrap_or(BitDepth::Eight)
+}
+
+fn check_dimensions(images: &[&DynamicImage]) -> Result<()> {
+    if images
+        .windows(2)
+        .any(|pair| pair[0].dimensions() != pair[1].dimensions())
+    {
+        bail!("Input images are not the same size!");
+    }
+
+    Ok(())
+}
+
+/// Converts specular and glossiness textures to metallic and roughness
+/// textures which look the same
+///
+/// Uses the energy-conserving conversion from the glTF
+/// `KHR_materials_pbrSpecularGlossiness` extension's reference converter.
+/// Glossiness is read from `glossiness` if given, or otherwise from the
+/// alpha channel of the specular texture.
+pub fn specular_to_metallic(
+    textures: &SpecularGlossiness,
+    glossiness: Option<&DynamicImage>,
+) -> Result<MetallicRoughness> {
+    let mut images = vec![&textures.diffuse, &textures.specular_glossiness];
+    images.extend(glossiness);
+    check_dimensions(&images)?;
+
+    if glossiness.is_none() && !has_alpha(&textures.specular_glossiness) {
+        bail!("Specular image does not have an alpha channel, and no glossiness image was given!");
+    }
+
+    let depth = deepest(&images);
+    let (width, height) = textures.diffuse.dimensions();
+    let diffuse = textures.diffuse.to_rgba32f();
+    let specular = textures.specular_glossiness.to_rgba32f();
+    let glossiness = glossiness.map(DynamicImage::to_rgba32f);
+
+    let mut base_color = Rgba32FImage::new(width, height);
+    let mut metallic = Rgba32FImage::new(width, height);
+    let mut roughness = Rgba32FImage::new(width, height);
+
+    for (x_position, y_position, diffuse_pixel) in diffuse.enumerate_pixels() {
+        let specular_pixel = specular.get_pixel(x_position, y_position);
+        let glossiness_value = match &glossiness {
+            Some(glossiness) => glossiness.get_pixel(x_position, y_position)[0],
+            None => specular_pixel[3],
+        };
+
+        let diffuse_colour = linear_colour(diffuse_pixel);
+        let specular_colour = linear_colour(specular_pixel);
+
+        let one_minus_specular_strength = 1.0 - max_component(specular_colour);
+        let metallic_value = solve_metallic(
+            perceived_brightness(diffuse_colour),
+            perceived_brightness(specular_colour),
+            one_minus_specular_strength,
+        );
+
+        let mut base_colour = [0.0; 3];
+
+        for (index, base) in base_colour.iter_mut().enumerate() {
+            let from_diffuse = diffuse_colour[index] * one_minus_specular_strength
+                / (1.0 - DIELECTRIC_SPECULAR)
+                / (1.0 - metallic_value).max(EPSILON);
+            let from_specular = (specular_colour[index]
+                - DIELECTRIC_SPECULAR * (1.0 - metallic_value))
+                / metallic_value.max(EPSILON);
+            let blend = metallic_value * metallic_value;
+
+            *base = from_diffuse + (from_specular - from_diffuse) * blend;
+        }
+
+        base_color.put_pixel(
+            x_position,
+            y_position,
+            srgb_pixel(base_colour, diffuse_pixel[3]),
+        );
+        metallic.put_pixel(x_position, y_position, grey_pixel(metallic_value));
+        roughness.put_pixel(x_position, y_position, grey_pixel(1.0 - glossiness_value));
+    }
+
+    Ok(MetallicRoughness {
+        base_color: to_depth(base_color, depth, colour_channels(&textures.diffuse)),
+        metallic: to_depth(metallic, depth, 1),
+        roughness: to_depth(roughness, depth, 1),
+    })
+}
+
+/// Converts metallic and roughness textures to specular and glossiness
+/// textures which look the same
+///
+/// The reverse of [`specular_to_metallic`], with glossiness written to the
+/// alpha channel of the specular texture.
+pub fn metallic_to_specular(textures: &MetallicRoughness) -> Result<SpecularGlossiness> {
+    check_dimensions(&[
+        &textures.base_color,
+        &textures.metallic,
+        &textures.roughness,
+    ])?;
+
+    let depth = deepest(&[
+        &textures.base_color,
+        &textures.metallic,
+        &textures.roughness,
+    ]);
+    let (width, height) = textures.base_color.dimensions();
+    let base_color = textures.base_color.to_rgba32f();
+    let metallic = textures.metallic.to_rgba32f();
+    let roughness = textures.roughness.to_rgba32f();
+
+    let mut diffuse = Rgba32FImage::new(width, height);
+    let mut specular = Rgba32FImage::new(width, height);
+
+    for (x_position, y_position, base_pixel) in base_color.enumerate_pixels() {
+        let metallic_value = unit(metallic.get_pixel(x_position, y_position)[0]);
+        let roughness_value = roughness.get_pixel(x_position, y_position)[0];
+
+        let base_colour = linear_colour(base_pixel);
+
+        let mut specular_colour = [0.0; 3];
+
+        for (index, specular) in specular_colour.iter_mut().enumerate() {
+            *specular =
+                DIELECTRIC_SPECULAR + (base_colour[index] - DIELECTRIC_SPECULAR) * metallic_value;
+        }
+
+        // Specular workflow shaders darken diffuse by the strength of the
+        // specular, which needs undoing here to keep the same brightness
+        let one_minus_specular_strength = (1.0 - max_component(specular_colour)).max(EPSILON);
+        let mut diffuse_colour = [0.0; 3];
+
+        for (index, diffuse) in diffuse_colour.iter_mut().enumerate() {
+            *diffuse = base_colour[index] * (1.0 - DIELECTRIC_SPECULAR) * (1.0 - metallic_value)
+                / one_minus_specular_strength;
+        }
+
+        diffuse.put_pixel(
+            x_position,
+            y_position,
+            srgb_pixel(diffuse_colour, base_pixel[3]),
+        );
+        specular.put_pixel(
+            x_position,
+            y_position,
+            srgb_pixel(specular_colour, 1.0 - roughness_value),
+        );
+    }
+
+    Ok(SpecularGlossiness {
+        diffuse: to_depth(diffuse, depth, colour_channels(&textures.base_color)),
+        specular_glossiness: to_depth(specular, depth, 4),
+    })
+}
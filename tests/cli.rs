@@ -0,0 +1,880 @@
+//! Runs the `matknife` binary on texture files in temporary directories,
+//! checking the files it writes, what it reports and its exit codes
+
+mod common;
+
+use common::{Pattern, TempDir};
+use image::{ColorType, DynamicImage};
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Runs `matknife` in a directory
+fn matknife(directory: &Path, arguments: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_matknife"))
+        .args(arguments)
+        .current_dir(directory)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap()
+}
+
+/// Runs `matknife` in a directory, failing the test unless it succeeds
+fn run(directory: &Path, arguments: &[&str]) -> String {
+    let output = matknife(directory, arguments);
+
+    assert!(
+        output.status.success(),
+        "matknife {:?} failed with {:?}: {}",
+        arguments,
+        output.status.code(),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn exit_code(directory: &Path, arguments: &[&str]) -> Option<i32> {
+    matknife(directory, arguments).status.code()
+}
+
+/// A Unity MetallicSmoothness texture, greyscale with smoothness in alpha
+fn metallic_smoothness() -> DynamicImage {
+    let metallic = common::fixture(Pattern::Gradient, ColorType::L8, 16, 8);
+    let smoothness = common::fixture(Pattern::Noise(3), ColorType::L8, 16, 8);
+
+    let values = metallic
+        .as_bytes()
+        .iter()
+        .zip(smoothness.as_bytes())
+        .flat_map(|(metallic, smoothness)| [*metallic, *metallic, *metallic, *smoothness])
+        .collect();
+
+    DynamicImage::ImageRgba8(image::ImageBuffer::from_raw(16, 8, values).unwrap())
+}
+
+fn save(image: &DynamicImage, path: impl AsRef<Path>) {
+    image.save(path).unwrap();
+}
+
+#[test]
+fn split_then_merge_gives_back_the_texture() {
+    let directory = TempDir::new("cli-split-merge");
+    let original = metallic_smoothness();
+    save(&original, directory.join("RustyMetallicSmoothness.png"));
+
+    let output = run(directory.path(), &["split", "RustyMetallicSmoothness.png"]);
+
+    assert!(output.contains("RustyMetallic.png"), "{}", output);
+    assert!(output.contains("RustyRoughness.png"), "{}", output);
+
+    std::fs::remove_file(directory.join("RustyMetallicSmoothness.png")).unwrap();
+
+    run(
+        directory.path(),
+        &["merge", "RustyMetallic.png", "RustyRoughness.png"],
+    );
+
+    let merged = image::open(directory.join("RustyMetallicSmoothness.png")).unwrap();
+
+    assert_eq!(merged.to_rgba8().as_raw(), original.to_rgba8().as_raw());
+}
+
+#[test]
+fn merge_keeps_separators_in_the_names_of_texture_sets() {
+    let directory = TempDir::new("cli-merge-separator");
+    save(
+        &common::fixture(Pattern::Gradient, ColorType::L8, 4, 4),
+        directory.join("Rusty_Metallic.png"),
+    );
+    save(
+        &common::fixture(Pattern::Edges, ColorType::L8, 4, 4),
+        directory.join("Rusty_Roughness.png"),
+    );
+
+    run(directory.path(), &["merge", "."]);
+
+    assert!(directory.join("Rusty_MetallicSmoothness.png").exists());
+    assert!(!directory.join("RustyMetallicSmoothness.png").exists());
+}
+
+#[test]
+fn split_works_on_directories_of_16_bit_files() {
+    let directory = TempDir::new("cli-split-16");
+    let packed = common::fixture(Pattern::Noise(5), ColorType::Rgba16, 9, 4);
+    save(&packed, directory.join("SteelMaskMap.png"));
+    save(&packed, directory.join("IronMaskMap.png"));
+    save(&packed, directory.join("Unrelated.png"));
+
+    run(directory.path(), &["split", "--layout", "hdrp", "."]);
+
+    for name in ["Steel", "Iron"] {
+        for map in ["Metallic", "Occlusion", "DetailMask", "Roughness"] {
+            let image = image::open(directory.join(format!("{}{}.png", name, map))).unwrap();
+
+            assert_eq!(image.color(), ColorType::L16, "{}{}", name, map);
+        }
+    }
+
+    assert!(!directory.join("UnrelatedMetallic.png").exists());
+}
+
+#[test]
+fn convert_set_renames_and_repacks_textures() {
+    let directory = TempDir::new("cli-convert-set");
+    save(
+        &metallic_smoothness(),
+        directory.join("RustyMetallicSmoothness.png"),
+    );
+    save(
+        &common::fixture(Pattern::Gradient, ColorType::Rgb8, 16, 8),
+        directory.join("RustyAlbedo.png"),
+    );
+    save(
+        &common::fixture(Pattern::Edges, ColorType::Rgb8, 16, 8),
+        directory.join("RustyNormal.png"),
+    );
+
+    run(
+        directory.path(),
+        &["convert-set", "--from", "unity", "--to", "unreal", "."],
+    );
+
+    for name in ["T_Rusty_ORM.png", "T_Rusty_D.png", "T_Rusty_N.png"] {
+        assert!(directory.join(name).exists(), "{} was not written", name);
+    }
+
+    // Unreal normal maps point green down
+    let normal = image::open(directory.join("T_Rusty_N.png"))
+        .unwrap()
+        .to_rgb8();
+    let original = common::fixture(Pattern::Edges, ColorType::Rgb8, 16, 8).to_rgb8();
+
+    for (flipped, pixel) in normal.pixels().zip(original.pixels()) {
+        assert_eq!(flipped[1], 255 - pixel[1]);
+    }
+}
+
+#[test]
+fn json_reports_every_file() {
+    let directory = TempDir::new("cli-json");
+    save(
+        &metallic_smoothness(),
+        directory.join("RustyMetallicSmoothness.png"),
+    );
+
+    let output = run(
+        directory.path(),
+        &["split", "--format", "json", "RustyMetallicSmoothness.png"],
+    );
+
+    assert_eq!(output.lines().count(), 1, "{}", output);
+    assert!(output.starts_with("{\"records\":[{"), "{}", output);
+    assert!(output.contains("\"kind\":\"metallic\""), "{}", output);
+    assert!(output.contains("\"kind\":\"roughness\""), "{}", output);
+    assert!(
+        output
+            .trim_end()
+            .ends_with("\"succeeded\":1,\"failed\":0,\"error\":null,\"exit_code\":0}"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn exit_codes_say_how_commands_failed() {
+    let directory = TempDir::new("cli-exit-codes");
+    save(
+        &metallic_smoothness(),
+        directory.join("GoodMetallicSmoothness.png"),
+    );
+    save(
+        &common::fixture(Pattern::Gradient, ColorType::Rgb8, 16, 8),
+        directory.join("OpaqueMetallicSmoothness.png"),
+    );
+
+    let directory = directory.path();
+
+    assert_eq!(exit_code(directory, &["split", "--bogus"]), Some(2));
+    assert_eq!(exit_code(directory, &["split", "Missing.png"]), Some(3));
+    assert_eq!(
+        exit_code(
+            directory,
+            &["normal", "--from", "opengl", "--to", "directx", ".."]
+        ),
+        Some(1)
+    );
+    assert_eq!(
+        exit_code(directory, &["split", "OpaqueMetallicSmoothness.png"]),
+        Some(1)
+    );
+    assert_eq!(
+        exit_code(
+            directory,
+            &[
+                "split",
+                "OpaqueMetallicSmoothness.png",
+                "GoodMetallicSmoothness.png"
+            ]
+        ),
+        Some(4)
+    );
+    assert_eq!(
+        exit_code(
+            directory,
+            &[
+                "split",
+                "--default-smoothness",
+                "0.5",
+                "OpaqueMetallicSmoothness.png"
+            ]
+        ),
+        Some(0)
+    );
+}
+
+#[test]
+fn lint_fails_files_with_errors() {
+    let directory = TempDir::new("cli-lint");
+    save(
+        &common::fixture(Pattern::Edges, ColorType::L8, 16, 8),
+        directory.join("GoodMetallic.png"),
+    );
+    save(
+        &common::fixture(Pattern::Gradient, ColorType::L8, 16, 8),
+        directory.join("BadMetallic.png"),
+    );
+    save(
+        &DynamicImage::new_luma8(16, 8),
+        directory.join("BadRoughness.png"),
+    );
+
+    assert_eq!(
+        exit_code(directory.path(), &["lint", "GoodMetallic.png"]),
+        Some(0)
+    );
+
+    let output = matknife(directory.path(), &["lint", "--format", "json", "."]);
+    let report = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(output.status.code(), Some(4), "{}", report);
+    assert_eq!(
+        report.matches("\"severity\":\"error\"").count(),
+        2,
+        "{}",
+        report
+    );
+    assert!(
+        report.contains("\"succeeded\":1,\"failed\":2"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn merge_reads_tinted_roughness_from_the_chosen_channel() {
+    let directory = TempDir::new("cli-channels");
+    save(
+        &common::fixture(Pattern::Edges, ColorType::L8, 2, 1),
+        directory.join("TintMetallic.png"),
+    );
+    save(
+        &DynamicImage::ImageRgb8(
+            image::ImageBuffer::from_raw(2, 1, vec![200, 100, 50, 10, 20, 30]).unwrap(),
+        ),
+        directory.join("TintRoughness.png"),
+    );
+
+    let output = matknife(
+        directory.path(),
+        &["merge", "TintMetallic.png", "TintRoughness.png"],
+    );
+
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("is not greyscale"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    for (channel, roughness) in [("r", [200, 10]), ("max", [200, 30]), ("avg", [117, 20])] {
+        run(
+            directory.path(),
+            &[
+                "merge",
+                "--roughness-channel",
+                channel,
+                "TintMetallic.png",
+                "TintRoughness.png",
+            ],
+        );
+
+        let merged = image::open(directory.join("TintMetallicSmoothness.png"))
+            .unwrap()
+            .to_rgba8();
+
+        assert_eq!(
+            [merged.get_pixel(0, 0)[3], merged.get_pixel(1, 0)[3]],
+            roughness.map(|roughness| 255 - roughness),
+            "--roughness-channel {}",
+            channel
+        );
+    }
+}
+
+#[test]
+fn merge_resizes_maps_of_different_sizes_when_asked() {
+    let directory = TempDir::new("cli-resize");
+    save(
+        &common::fixture(Pattern::Edges, ColorType::L8, 16, 8),
+        directory.join("HalfMetallic.png"),
+    );
+    save(
+        &common::fixture(Pattern::Gradient, ColorType::L8, 8, 4),
+        directory.join("HalfRoughness.png"),
+    );
+
+    let files = ["HalfMetallic.png", "HalfRoughness.png"];
+
+    assert_eq!(
+        exit_code(directory.path(), &["merge", files[0], files[1]]),
+        Some(1)
+    );
+
+    for (target, size) in [
+        ("larger", (16, 8)),
+        ("roughness", (8, 4)),
+        ("smaller", (8, 4)),
+        ("32x32", (32, 32)),
+    ] {
+        for filter in ["nearest", "bilinear", "lanczos", "box"] {
+            let output = matknife(
+                directory.path(),
+                &[
+                    "merge",
+                    "--resize-to",
+                    target,
+                    "--resize-filter",
+                    filter,
+                    files[0],
+                    files[1],
+                ],
+            );
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            assert!(output.status.success(), "{} {}: {}", target, filter, stderr);
+            assert!(
+                stderr.contains("Resizing"),
+                "{} {}: {}",
+                target,
+                filter,
+                stderr
+            );
+
+            let merged = image::open(directory.join("HalfMetallicSmoothness.png")).unwrap();
+
+            assert_eq!(
+                (merged.width(), merged.height()),
+                size,
+                "{} {}",
+                target,
+                filter
+            );
+        }
+    }
+}
+
+#[test]
+fn output_options_shrink_every_file_written() {
+    let directory = TempDir::new("cli-output-size");
+    save(
+        &common::fixture(Pattern::Noise(9), ColorType::Rgba8, 40, 24),
+        directory.join("BigMetallicSmoothness.png"),
+    );
+
+    for (arguments, size) in [
+        (&["--downscale", "4"][..], (10, 6)),
+        (&["--max-size", "16"][..], (16, 10)),
+        (&["--max-size", "20", "--power-of-two", "up"][..], (16, 16)),
+        (&["--power-of-two", "down"][..], (32, 16)),
+    ] {
+        let mut command = vec!["split"];
+        command.extend(arguments);
+        command.push("BigMetallicSmoothness.png");
+
+        run(directory.path(), &command);
+
+        for map in ["Metallic", "Roughness"] {
+            let image = image::open(directory.join(format!("Big{}.png", map))).unwrap();
+
+            assert_eq!(
+                (image.width(), image.height()),
+                size,
+                "{} with {:?}",
+                map,
+                arguments
+            );
+        }
+    }
+
+    // Normals averaged across a checkerboard still point the same way
+    let normals = DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(
+        8,
+        8,
+        |x_position, y_position| {
+            if (x_position + y_position) % 2 == 0 {
+                image::Rgb([218, 128, 218])
+            } else {
+                image::Rgb([37, 128, 218])
+            }
+        },
+    ));
+    save(&normals, directory.join("BumpyNormal.png"));
+
+    run(
+        directory.path(),
+        &[
+            "normal",
+            "--from",
+            "opengl",
+            "--to",
+            "opengl",
+            "--max-size",
+            "4",
+            "BumpyNormal.png",
+            "--output",
+            "SmallNormal.png",
+        ],
+    );
+
+    let small = image::open(directory.join("SmallNormal.png"))
+        .unwrap()
+        .to_rgb8();
+
+    assert_eq!(small.dimensions(), (4, 4));
+
+    for pixel in small.pixels() {
+        assert_eq!(pixel.0, [128, 128, 255]);
+    }
+}
+
+#[test]
+fn merge_keeps_data_tagged_as_srgb_as_it_is() {
+    let directory = TempDir::new("cli-tagged");
+    let roughness = common::fixture(Pattern::Gradient, ColorType::L8, 16, 8);
+
+    save(
+        &common::fixture(Pattern::Edges, ColorType::L8, 16, 8),
+        directory.join("TaggedMetallic.png"),
+    );
+    matknife::image_file::save(
+        &roughness,
+        &directory.join("TaggedRoughness.png"),
+        Some(matknife::color::ColorSpace::Srgb),
+    )
+    .unwrap();
+
+    let files = ["TaggedMetallic.png", "TaggedRoughness.png"];
+    let smoothness = || -> Vec<u8> {
+        image::open(directory.join("TaggedMetallicSmoothness.png"))
+            .unwrap()
+            .to_rgba8()
+            .pixels()
+            .map(|pixel| 255 - pixel[3])
+            .collect()
+    };
+
+    let output = matknife(directory.path(), &["merge", files[0], files[1]]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("is tagged as sRGB"), "{}", stderr);
+    assert_eq!(smoothness(), roughness.as_bytes());
+
+    run(
+        directory.path(),
+        &["merge", "--input-colorspace", "srgb", files[0], files[1]],
+    );
+
+    assert_ne!(smoothness(), roughness.as_bytes());
+}
+
+#[test]
+fn convert_set_never_overwrites_its_input_files() {
+    let directory = TempDir::new("cli-overwrite");
+    let normal = common::fixture(Pattern::Edges, ColorType::Rgb8, 4, 4);
+    save(&normal, directory.join("RockNormal.png"));
+
+    assert_eq!(
+        exit_code(
+            directory.path(),
+            &["convert-set", "--from", "unreal", "--to", "usd", "."]
+        ),
+        Some(1)
+    );
+
+    let unchanged = image::open(directory.join("RockNormal.png")).unwrap();
+    assert_eq!(unchanged.as_bytes(), normal.as_bytes());
+
+    run(
+        directory.path(),
+        &[
+            "convert-set",
+            "--from",
+            "unreal",
+            "--to",
+            "usd",
+            "--output-dir",
+            "converted",
+            ".",
+        ],
+    );
+
+    assert!(directory.join("converted/RockNormal.png").exists());
+}
+
+#[test]
+fn convert_never_overwrites_its_input_file() {
+    let directory = TempDir::new("cli-convert-overwrite");
+    let mask_map = common::fixture(Pattern::Gradient, ColorType::Rgba8, 4, 4);
+    save(&mask_map, directory.join("RustyMaskMap.png"));
+
+    assert_eq!(
+        exit_code(
+            directory.path(),
+            &[
+                "convert",
+                "RustyMaskMap.png",
+                "--from",
+                "hdrp",
+                "--to",
+                "hdrp"
+            ]
+        ),
+        Some(1)
+    );
+
+    let unchanged = image::open(directory.join("RustyMaskMap.png")).unwrap();
+    assert_eq!(unchanged.as_bytes(), mask_map.as_bytes());
+
+    run(
+        directory.path(),
+        &[
+            "convert",
+            "RustyMaskMap.png",
+            "--from",
+            "hdrp",
+            "--to",
+            "hdrp",
+            "--output-dir",
+            "converted",
+        ],
+    );
+
+    assert!(directory.join("converted/RustyMaskMap.png").exists());
+}
+
+#[test]
+fn output_dir_keeps_the_subdirectories_of_input_files() {
+    let directory = TempDir::new("cli-output-dir");
+
+    for (folder, pattern) in [("rock", Pattern::Gradient), ("moss", Pattern::Edges)] {
+        std::fs::create_dir_all(directory.join(folder)).unwrap();
+        save(
+            &common::fixture(pattern, ColorType::Rgba8, 4, 4),
+            directory.join(folder).join("GroundMetallicSmoothness.png"),
+        );
+    }
+
+    run(
+        directory.path(),
+        &["split", "-r", "--output-dir", "out", "."],
+    );
+
+    for (folder, pattern) in [("rock", Pattern::Gradient), ("moss", Pattern::Edges)] {
+        let metallic = image::open(
+            directory
+                .join("out")
+                .join(folder)
+                .join("GroundMetallic.png"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            common::grey(&metallic),
+            common::grey(&common::fixture(pattern, ColorType::L8, 4, 4)),
+            "{}",
+            folder
+        );
+    }
+}
+
+#[test]
+fn dds_files_keep_the_alpha_of_greyscale_images() {
+    let directory = TempDir::new("cli-dds-alpha");
+    let grey_alpha = DynamicImage::ImageRgba8(image::ImageBuffer::from_pixel(
+        4,
+        4,
+        image::Rgba([200, 200, 200, 50]),
+    ));
+    save(&grey_alpha, directory.join("GreyAlpha.png"));
+
+    let read_dds = |file: &str| -> (u32, Vec<u8>) {
+        let data = std::fs::read(directory.join(file)).unwrap();
+
+        (
+            u32::from_le_bytes(data[128..132].try_into().unwrap()),
+            data[148..].to_vec(),
+        )
+    };
+
+    let pack = |file: &str, extra: &[&str]| {
+        let mut arguments = vec!["pack", "-l", "0:r", "-a", "0:a", "-o", file];
+        arguments.extend(extra);
+        arguments.push("GreyAlpha.png");
+
+        run(directory.path(), &arguments);
+    };
+
+    // Chosen automatically, greyscale with alpha is BC7
+    pack("Auto.dds", &[]);
+    assert_eq!(read_dds("Auto.dds").0, 98);
+
+    // BC5 holds luma in red and alpha in green
+    pack("Bc5.dds", &["--dds-format", "bc5"]);
+    let (format, block) = read_dds("Bc5.dds");
+
+    assert_eq!(format, 83);
+    assert_eq!([block[0], block[8]], [200, 50]);
+}
+
+#[test]
+fn files_given_more_than_once_are_split_once() {
+    let directory = TempDir::new("cli-duplicates");
+    save(
+        &metallic_smoothness(),
+        directory.join("RustyMetallicSmoothness.png"),
+    );
+
+    let output = run(
+        directory.path(),
+        &[
+            "split",
+            "--format",
+            "json",
+            "RustyMetallicSmoothness.png",
+            "./RustyMetallicSmoothness.png",
+            ".",
+            "*.png",
+            "Rusty*",
+        ],
+    );
+
+    assert!(
+        output.contains("\"succeeded\":1,\"failed\":0"),
+        "{}",
+        output
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn recursive_batches_do_not_follow_links_to_directories() {
+    let directory = TempDir::new("cli-directory-links");
+    std::fs::create_dir(directory.join("rusty")).unwrap();
+    save(
+        &metallic_smoothness(),
+        directory.join("rusty/RustyMetallicSmoothness.png"),
+    );
+
+    // Each link leads back up, so following them would walk forever
+    for link in ["parent", "again"] {
+        std::os::unix::fs::symlink("..", directory.join("rusty").join(link)).unwrap();
+    }
+
+    let output = run(
+        directory.path(),
+        &["split", "--format", "json", "--recursive", "."],
+    );
+
+    assert!(
+        output.contains("\"succeeded\":1,\"failed\":0"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn split_will_not_read_smoothness_from_missing_alpha() {
+    let directory = TempDir::new("cli-missing-alpha");
+    save(
+        &common::fixture(Pattern::Gradient, ColorType::Rgb8, 16, 8),
+        directory.join("OpaqueMetallicSmoothness.png"),
+    );
+    save(
+        &common::fixture(Pattern::Gradient, ColorType::L8, 16, 8),
+        directory.join("Smoothness.png"),
+    );
+
+    for arguments in [
+        &["--smoothness-channel", "a"][..],
+        &[
+            "--smoothness-file",
+            "Smoothness.png",
+            "--smoothness-channel",
+            "a",
+        ][..],
+    ] {
+        let mut command = vec!["split"];
+        command.extend(arguments);
+        command.push("OpaqueMetallicSmoothness.png");
+
+        let output = matknife(directory.path(), &command);
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        assert_eq!(output.status.code(), Some(1), "{:?}", arguments);
+        assert!(
+            stdout.contains("has no alpha channel"),
+            "{:?}: {}",
+            arguments,
+            stdout
+        );
+        assert!(!directory.join("OpaqueRoughness.png").exists());
+    }
+}
+
+#[test]
+fn shrunk_packed_textures_keep_the_roughness_of_lost_bumps() {
+    let directory = TempDir::new("cli-shrunk-toksvig");
+    save(
+        &common::fixture(Pattern::Edges, ColorType::L8, 8, 8),
+        directory.join("BumpyMetallic.png"),
+    );
+    save(
+        &DynamicImage::ImageLuma8(image::ImageBuffer::from_pixel(8, 8, image::Luma([77]))),
+        directory.join("BumpyRoughness.png"),
+    );
+    // Normals tilted left and right in a checkerboard, which average out
+    // to flat in a smaller texture
+    save(
+        &DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(
+            8,
+            8,
+            |x_position, y_position| {
+                if (x_position + y_position) % 2 == 0 {
+                    image::Rgb([218, 128, 218])
+                } else {
+                    image::Rgb([37, 128, 218])
+                }
+            },
+        )),
+        directory.join("BumpyNormal.png"),
+    );
+
+    let smoothness = |extra: &[&str]| -> Vec<u8> {
+        let mut arguments = vec!["merge", "--downscale", "2"];
+        arguments.extend(extra);
+        arguments.extend(["BumpyMetallic.png", "BumpyRoughness.png", "BumpyNormal.png"]);
+
+        run(directory.path(), &arguments);
+
+        let merged = image::open(directory.join("BumpyMetallicSmoothness.png"))
+            .unwrap()
+            .to_rgba8();
+
+        assert_eq!(merged.dimensions(), (4, 4));
+
+        merged.pixels().map(|pixel| pixel[3]).collect()
+    };
+
+    assert!(smoothness(&["--no-toksvig"])
+        .iter()
+        .all(|alpha| *alpha == 178));
+    assert!(
+        smoothness(&[]).iter().all(|alpha| *alpha < 178),
+        "{:?}",
+        smoothness(&[])
+    );
+}
+
+/// Stands in for `toktx`, keeping the arguments and PNG files it is given
+/// next to itself and writing a placeholder KTX2 file
+#[cfg(unix)]
+const FAKE_TOKTX: &str = r#"#!/bin/sh
+kept=$(dirname "$0")
+echo "$@" > "$kept/arguments"
+for argument; do
+    case "$argument" in
+        *.ktx2) output=$argument ;;
+        *.png) cp "$argument" "$kept/" ;;
+    esac
+done
+printf encoded > "$output"
+"#;
+
+#[cfg(unix)]
+#[test]
+fn basis_ktx2_files_are_encoded_by_toktx() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let directory = TempDir::new("cli-toktx");
+    save(
+        &metallic_smoothness(),
+        directory.join("RustyMetallicSmoothness.png"),
+    );
+
+    let toktx = directory.join("toktx");
+    std::fs::write(&toktx, FAKE_TOKTX).unwrap();
+    std::fs::set_permissions(&toktx, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let split = |toktx: &Path| {
+        Command::new(env!("CARGO_BIN_EXE_matknife"))
+            .args([
+                "split",
+                "--extension",
+                "ktx2",
+                "--ktx2-encoding",
+                "uastc",
+                "--mipmaps",
+                "RustyMetallicSmoothness.png",
+            ])
+            .current_dir(directory.path())
+            .env("MATKNIFE_TOKTX", toktx)
+            .env_remove("RUST_LOG")
+            .output()
+            .unwrap()
+    };
+
+    let output = split(&toktx);
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read(directory.join("RustyRoughness.ktx2")).unwrap(),
+        b"encoded"
+    );
+
+    // Roughness is data, and 16x8 has 5 mip levels down to 1x1
+    let arguments = std::fs::read_to_string(directory.join("arguments")).unwrap();
+
+    assert!(
+        arguments.starts_with("--t2 --encode uastc --assign_oetf linear --mipmap --levels 5 "),
+        "{}",
+        arguments
+    );
+
+    for (level, size) in [(0, (16, 8)), (1, (8, 4)), (4, (1, 1))] {
+        let image = image::open(directory.join(format!("level{}.png", level))).unwrap();
+
+        assert_eq!((image.width(), image.height()), size, "level {}", level);
+    }
+
+    let output = split(&directory.join("missing"));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("needs toktx"), "{}", stdout);
+}
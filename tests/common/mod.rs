@@ -0,0 +1,282 @@
+//! Fixtures and helpers shared by the integration tests
+//!
+//! Fixtures are synthetic images made from a [`Pattern`], so that tests
+//! need no image files of their own. Golden images live in `tests/golden`,
+//! and are written again from what the code produces now by running the
+//! tests with `MATKNIFE_BLESS=1`.
+
+#![allow(dead_code)]
+
+use image::{ColorType, DynamicImage, ImageBuffer};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A small xorshift generator, so that every run checks the same values
+pub struct Noise(u32);
+
+impl Noise {
+    /// Starts a generator, which must not be seeded with 0
+    pub fn new(seed: u32) -> Self {
+        Noise(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u16 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+
+        (self.0 >> 16) as u16
+    }
+
+    /// A number from 0 up to, but not including, `limit`
+    pub fn below(&mut self, limit: usize) -> usize {
+        self.next() as usize % limit
+    }
+
+    /// One of `items`
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// The greyscale values of a fixture
+#[derive(Clone, Copy, Debug)]
+pub enum Pattern {
+    /// Black on the left to white on the right
+    Gradient,
+    /// Black at the top to white at the bottom
+    VerticalGradient,
+    /// Random values, and random alpha, from a seed
+    Noise(u32),
+    /// A checkerboard of black and white, the values most likely to be
+    /// clamped or wrapped the wrong way
+    Edges,
+}
+
+/// The kinds of image separate maps are commonly stored as
+pub const COLOR_TYPES: &[ColorType] = &[
+    ColorType::L8,
+    ColorType::La8,
+    ColorType::Rgb8,
+    ColorType::Rgba8,
+    ColorType::L16,
+    ColorType::La16,
+    ColorType::Rgb16,
+    ColorType::Rgba16,
+    ColorType::Rgb32F,
+    ColorType::Rgba32F,
+];
+
+fn ramp(position: u32, size: u32) -> u16 {
+    if size < 2 {
+        0
+    } else {
+        (position as u64 * u16::MAX as u64 / (size as u64 - 1)) as u16
+    }
+}
+
+/// The 16-bit greyscale and alpha values of every pixel of a pattern
+fn samples(pattern: Pattern, width: u32, height: u32) -> Vec<(u16, u16)> {
+    let mut noise = match pattern {
+        Pattern::Noise(seed) => Some(Noise::new(seed)),
+        _ => None,
+    };
+
+    let mut samples = Vec::with_capacity((width * height) as usize);
+
+    for y_position in 0..height {
+        for x_position in 0..width {
+            samples.push(match (pattern, &mut noise) {
+                (Pattern::Gradient, _) => (ramp(x_position, width), u16::MAX),
+                (Pattern::VerticalGradient, _) => (ramp(y_position, height), u16::MAX),
+                (Pattern::Edges, _) if (x_position + y_position) % 2 == 0 => (0, u16::MAX),
+                (Pattern::Edges, _) => (u16::MAX, u16::MAX),
+                (_, Some(noise)) => (noise.next(), noise.next()),
+                (Pattern::Noise(_), None) => unreachable!(),
+            });
+        }
+    }
+
+    samples
+}
+
+/// Makes a greyscale map stored as `color_type`, with its value in every
+/// colour channel
+///
+/// 8-bit images hold the top 8 bits of the pattern's 16-bit values, so that
+/// every depth holds the same picture.
+pub fn fixture(pattern: Pattern, color_type: ColorType, width: u32, height: u32) -> DynamicImage {
+    let channels = color_type.channel_count() as usize;
+    let alpha = color_type.has_alpha();
+    let colour_channels = if alpha { channels - 1 } else { channels };
+
+    let mut values: Vec<u16> = Vec::with_capacity((width * height) as usize * channels);
+
+    for (grey, opacity) in samples(pattern, width, height) {
+        values.extend(std::iter::repeat_n(grey, colour_channels));
+
+        if alpha {
+            values.push(opacity);
+        }
+    }
+
+    let eight_bit = || values.iter().map(|value| (value >> 8) as u8).collect();
+    let float = || {
+        values
+            .iter()
+            .map(|value| *value as f32 / u16::MAX as f32)
+            .collect()
+    };
+
+    match color_type {
+        ColorType::L8 => {
+            DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, eight_bit()).unwrap())
+        }
+        ColorType::La8 => {
+            DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, eight_bit()).unwrap())
+        }
+        ColorType::Rgb8 => {
+            DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, eight_bit()).unwrap())
+        }
+        ColorType::Rgba8 => {
+            DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, eight_bit()).unwrap())
+        }
+        ColorType::L16 => {
+            DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, values).unwrap())
+        }
+        ColorType::La16 => {
+            DynamicImage::ImageLumaA16(ImageBuffer::from_raw(width, height, values).unwrap())
+        }
+        ColorType::Rgb16 => {
+            DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, values).unwrap())
+        }
+        ColorType::Rgba16 => {
+            DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, values).unwrap())
+        }
+        ColorType::Rgb32F => {
+            DynamicImage::ImageRgb32F(ImageBuffer::from_raw(width, height, float()).unwrap())
+        }
+        ColorType::Rgba32F => {
+            DynamicImage::ImageRgba32F(ImageBuffer::from_raw(width, height, float()).unwrap())
+        }
+        _ => panic!("No fixtures are made as {:?}", color_type),
+    }
+}
+
+/// Makes an RGBA image with a different pattern in each channel, as packed
+/// textures have
+pub fn packed_fixture(patterns: [Pattern; 4], width: u32, height: u32) -> DynamicImage {
+    let channels = patterns.map(|pattern| samples(pattern, width, height));
+
+    let values = (0..(width * height) as usize)
+        .flat_map(|pixel| {
+            channels
+                .iter()
+                .map(move |channel| (channel[pixel].0 >> 8) as u8)
+        })
+        .collect();
+
+    DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, values).unwrap())
+}
+
+/// The greyscale value of every pixel of a map, which is in red
+pub fn grey(image: &DynamicImage) -> Vec<u16> {
+    image.to_rgba16().pixels().map(|pixel| pixel[0]).collect()
+}
+
+/// The greyscale value of every pixel of a map, which is in red, as a
+/// float
+pub fn grey_f32(image: &DynamicImage) -> Vec<f32> {
+    image.to_rgba32f().pixels().map(|pixel| pixel[0]).collect()
+}
+
+/// The largest difference between two lists of values
+pub fn max_difference(first: &[f32], second: &[f32]) -> f32 {
+    assert_eq!(first.len(), second.len(), "different numbers of values");
+
+    first
+        .iter()
+        .zip(second)
+        .map(|(first, second)| (first - second).abs())
+        .fold(0.0, f32::max)
+}
+
+/// A directory of its own for one test, removed when it is dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "matknife-test-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Checks an image against the golden image `tests/golden/<name>.png`
+///
+/// With `MATKNIFE_BLESS` set, the golden image is written instead.
+pub fn check_golden(name: &str, image: &DynamicImage) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.png", name));
+
+    if env::var_os("MATKNIFE_BLESS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image.save(&path).unwrap();
+        return;
+    }
+
+    let golden = image::open(&path).unwrap_or_else(|error| {
+        panic!(
+            "Could not open golden image {:?}, run with MATKNIFE_BLESS=1 to write it: {}",
+            path, error
+        )
+    });
+
+    assert_eq!(
+        (image.color(), image.width(), image.height()),
+        (golden.color(), golden.width(), golden.height()),
+        "{} is a different kind or size of image from its golden image",
+        name
+    );
+
+    let differences = image
+        .as_bytes()
+        .iter()
+        .zip(golden.as_bytes())
+        .filter(|(value, golden)| value != golden)
+        .count();
+
+    assert_eq!(
+        differences, 0,
+        "{} differs from its golden image in {} bytes",
+        name, differences
+    );
+}
@@ -0,0 +1,190 @@
+//! Checks what `split`, `merge`, the texture set conventions and the
+//! workflow conversions produce against golden images
+//!
+//! Run with `MATKNIFE_BLESS=1` to write the golden images again after a
+//! change which is meant to alter them, and look over the changed files
+//! before committing them.
+
+mod common;
+
+use common::Pattern;
+use image::{ColorType, DynamicImage};
+use matknife::layout::{Map, LAYOUTS};
+use matknife::normal::NormalConvention;
+use matknife::texture_set::{self, CONVENTIONS};
+use matknife::{workflow, RoughnessCurve};
+use std::collections::BTreeMap;
+
+const WIDTH: u32 = 16;
+const HEIGHT: u32 = 8;
+
+/// A different pattern for each map, so that maps which end up in the
+/// wrong channel are caught
+const PATTERNS: [Pattern; 4] = [
+    Pattern::Gradient,
+    Pattern::Noise(0x5eed),
+    Pattern::Edges,
+    Pattern::VerticalGradient,
+];
+
+/// A name for golden images, made of plain lowercase words
+fn golden_name(parts: &[&str]) -> String {
+    parts
+        .iter()
+        .map(|part| {
+            part.trim_matches(['_', '-'])
+                .to_ascii_lowercase()
+                .replace(['_', '+', ' '], "-")
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[test]
+fn merge_matches_golden_images() {
+    for layout in LAYOUTS {
+        let images = layout
+            .maps
+            .iter()
+            .zip(PATTERNS)
+            .map(|(_, pattern)| Some(common::fixture(pattern, ColorType::L8, WIDTH, HEIGHT)))
+            .collect();
+
+        let packed = layout.merge(images, RoughnessCurve::Linear).unwrap();
+
+        common::check_golden(&golden_name(&["merge", layout.name]), &packed);
+    }
+}
+
+#[test]
+fn split_matches_golden_images() {
+    let packed = common::packed_fixture(PATTERNS, WIDTH, HEIGHT);
+
+    for layout in LAYOUTS {
+        for (map, image) in layout
+            .split(packed.clone(), RoughnessCurve::Linear)
+            .unwrap()
+        {
+            common::check_golden(&golden_name(&["split", layout.name, map.name()]), &image);
+        }
+    }
+}
+
+#[test]
+fn roughness_curves_match_golden_images() {
+    let packed = common::packed_fixture(PATTERNS, WIDTH, HEIGHT);
+
+    for (curve, name) in [
+        (RoughnessCurve::Squared, "squared"),
+        (RoughnessCurve::Phong, "phong"),
+    ] {
+        for (map, image) in matknife::layout::UNITY
+            .split(packed.clone(), curve)
+            .unwrap()
+        {
+            if map == Map::Roughness {
+                common::check_golden(&golden_name(&["split", "unity", name]), &image);
+            }
+        }
+    }
+}
+
+/// Separate maps of a material, as USD-style files hold them
+fn material() -> BTreeMap<Map, (DynamicImage, NormalConvention)> {
+    let normal = common::packed_fixture(
+        [
+            Pattern::Gradient,
+            Pattern::VerticalGradient,
+            Pattern::Edges,
+            Pattern::Edges,
+        ],
+        WIDTH,
+        HEIGHT,
+    );
+
+    let maps = [
+        (
+            Map::BaseColor,
+            DynamicImage::ImageRgb8(common::packed_fixture(PATTERNS, WIDTH, HEIGHT).to_rgb8()),
+        ),
+        (Map::Normal, DynamicImage::ImageRgb8(normal.to_rgb8())),
+        (
+            Map::Metallic,
+            common::fixture(PATTERNS[0], ColorType::L8, WIDTH, HEIGHT),
+        ),
+        (
+            Map::Roughness,
+            common::fixture(PATTERNS[1], ColorType::L8, WIDTH, HEIGHT),
+        ),
+        (
+            Map::Occlusion,
+            common::fixture(PATTERNS[2], ColorType::L8, WIDTH, HEIGHT),
+        ),
+    ];
+
+    maps.into_iter()
+        .map(|(map, image)| (map, (image, NormalConvention::OpenGl)))
+        .collect()
+}
+
+#[test]
+fn conventions_match_golden_images() {
+    for convention in CONVENTIONS {
+        let converted =
+            texture_set::convert_maps(material(), convention, RoughnessCurve::Linear).unwrap();
+
+        for texture in converted {
+            common::check_golden(
+                &golden_name(&["convert", convention.name, texture.suffix]),
+                &texture.image,
+            );
+        }
+    }
+}
+
+#[test]
+fn workflow_conversions_match_golden_images() {
+    let specular = workflow::metallic_to_specular(&workflow::MetallicRoughness {
+        base_color: common::packed_fixture(PATTERNS, WIDTH, HEIGHT),
+        metallic: common::fixture(Pattern::Gradient, ColorType::L8, WIDTH, HEIGHT),
+        roughness: common::fixture(Pattern::Noise(0x5eed), ColorType::L8, WIDTH, HEIGHT),
+    })
+    .unwrap();
+
+    common::check_golden("metal2spec-diffuse", &specular.diffuse);
+    common::check_golden("metal2spec-specular", &specular.specular_glossiness);
+
+    let diffuse = common::packed_fixture(
+        [
+            Pattern::VerticalGradient,
+            Pattern::Gradient,
+            Pattern::Noise(0x5eed),
+            Pattern::Edges,
+        ],
+        WIDTH,
+        HEIGHT,
+    );
+    let specular_glossiness = common::packed_fixture(
+        [
+            Pattern::Gradient,
+            Pattern::Gradient,
+            Pattern::VerticalGradient,
+            Pattern::Noise(0xface),
+        ],
+        WIDTH,
+        HEIGHT,
+    );
+
+    let metallic = workflow::specular_to_metallic(
+        &workflow::SpecularGlossiness {
+            diffuse,
+            specular_glossiness,
+        },
+        None,
+    )
+    .unwrap();
+
+    common::check_golden("spec2metal-basecolor", &metallic.base_color);
+    common::check_golden("spec2metal-metallic", &metallic.metallic);
+    common::check_golden("spec2metal-roughness", &metallic.roughness);
+}
@@ -0,0 +1,546 @@
+//! Reads back the KTX2 and DDS files matknife writes, checking that they
+//! hold the pixels, formats, colour spaces and mip levels they should
+//!
+//! Compressed DDS blocks are decoded here as a GPU would decode them, from
+//! the published block formats rather than from matknife's encoder.
+
+mod common;
+
+use common::{Noise, Pattern};
+use image::{ColorType, DynamicImage, Rgba, RgbaImage};
+use matknife::dds::{self, Format};
+use matknife::ktx2::{self, Encoding};
+use matknife::mipmap::{self, Toksvig};
+use matknife::{ColorSpace, RoughnessCurve};
+
+/// What a KTX2 file says about its pixels
+struct Ktx2 {
+    vk_format: u32,
+    width: u32,
+    height: u32,
+    /// The transfer function of the data format descriptor, 1 for linear
+    /// and 2 for sRGB
+    transfer: u8,
+    /// The pixels of each mip level, largest first
+    levels: Vec<Vec<u8>>,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> usize {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap()) as usize
+}
+
+fn read_ktx2(data: &[u8]) -> Ktx2 {
+    assert_eq!(&data[..12], b"\xabKTX 20\xbb\r\n\x1a\n", "not a KTX2 file");
+
+    let level_count = read_u32(data, 40).max(1) as usize;
+    let descriptor = read_u32(data, 48) as usize;
+
+    let levels = (0..level_count)
+        .map(|level| {
+            let entry = 80 + 24 * level;
+            let offset = read_u64(data, entry);
+
+            data[offset..offset + read_u64(data, entry + 8)].to_vec()
+        })
+        .collect();
+
+    Ktx2 {
+        vk_format: read_u32(data, 12),
+        width: read_u32(data, 20),
+        height: read_u32(data, 24),
+        transfer: data[descriptor + 14],
+        levels,
+    }
+}
+
+#[test]
+fn ktx2_files_hold_greyscale_colour_maps_as_rgba() {
+    let grey = common::fixture(Pattern::Gradient, ColorType::L8, 8, 4);
+
+    let srgb = read_ktx2(
+        &ktx2::encode(
+            std::slice::from_ref(&grey),
+            ColorSpace::Srgb,
+            Encoding::None,
+        )
+        .unwrap(),
+    );
+
+    assert_eq!(srgb.vk_format, 43, "R8G8B8A8_SRGB");
+    assert_eq!(srgb.transfer, 2);
+    assert_eq!((srgb.width, srgb.height), (8, 4));
+    assert_eq!(srgb.levels[0], grey.to_rgba8().into_raw());
+
+    let linear = read_ktx2(
+        &ktx2::encode(
+            std::slice::from_ref(&grey),
+            ColorSpace::Linear,
+            Encoding::None,
+        )
+        .unwrap(),
+    );
+
+    assert_eq!(linear.vk_format, 9, "R8_UNORM");
+    assert_eq!(linear.transfer, 1);
+    assert_eq!(linear.levels[0], grey.as_bytes());
+}
+
+#[test]
+fn ktx2_files_hold_every_mip_level() {
+    let image = common::fixture(Pattern::Noise(7), ColorType::Rgba8, 16, 8);
+    let levels = mipmap::generate(&image, Some(ColorSpace::Linear), None, None);
+
+    let ktx2 = read_ktx2(&ktx2::encode(&levels, ColorSpace::Linear, Encoding::None).unwrap());
+
+    assert_eq!(ktx2.vk_format, 37, "R8G8B8A8_UNORM");
+    assert_eq!(ktx2.levels.len(), 5, "16x8 down to 1x1");
+
+    for (level, (stored, expected)) in ktx2.levels.iter().zip(&levels).enumerate() {
+        assert_eq!(
+            expected.width(),
+            (16 >> level as u32).max(1),
+            "width of level {}",
+            level
+        );
+        assert_eq!(stored, expected.as_bytes(), "pixels of level {}", level);
+    }
+}
+
+#[test]
+fn ktx2_files_keep_16_bit_and_float_images() {
+    let deep = common::fixture(Pattern::Gradient, ColorType::Rgb16, 8, 4);
+    let stored = read_ktx2(
+        &ktx2::encode(
+            std::slice::from_ref(&deep),
+            ColorSpace::Linear,
+            Encoding::None,
+        )
+        .unwrap(),
+    );
+
+    assert_eq!(stored.vk_format, 91, "R16G16B16A16_UNORM");
+
+    let expected: Vec<u8> = deep
+        .to_rgba16()
+        .into_raw()
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+
+    assert_eq!(stored.levels[0], expected);
+
+    let float = common::fixture(Pattern::Noise(3), ColorType::Rgb32F, 8, 4);
+    let stored = read_ktx2(
+        &ktx2::encode(
+            std::slice::from_ref(&float),
+            ColorSpace::Linear,
+            Encoding::None,
+        )
+        .unwrap(),
+    );
+
+    assert_eq!(stored.vk_format, 109, "R32G32B32A32_SFLOAT");
+
+    let expected: Vec<u8> = float
+        .to_rgba32f()
+        .into_raw()
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+
+    assert_eq!(stored.levels[0], expected);
+}
+
+/// Reads bits from a block, lowest first, as BC7 stores them
+struct Bits(u128, u32);
+
+impl Bits {
+    fn new(block: &[u8]) -> Self {
+        Bits(u128::from_le_bytes(block.try_into().unwrap()), 0)
+    }
+
+    fn take(&mut self, count: u32) -> u8 {
+        let value = (self.0 >> self.1) as u8 & ((1 << count) - 1) as u8;
+        self.1 += count;
+
+        value
+    }
+}
+
+fn expand_565(colour: u16) -> [u8; 3] {
+    let red = (colour >> 11) as u8 & 0x1f;
+    let green = (colour >> 5) as u8 & 0x3f;
+    let blue = colour as u8 & 0x1f;
+
+    [
+        (red << 3) | (red >> 2),
+        (green << 2) | (green >> 4),
+        (blue << 3) | (blue >> 2),
+    ]
+}
+
+/// Decodes the colours of a BC1 block, which in BC3 always have four
+/// colours whichever endpoint is larger
+fn decode_bc1(block: &[u8], always_four: bool) -> [[u8; 4]; 16] {
+    let first = u16::from_le_bytes([block[0], block[1]]);
+    let second = u16::from_le_bytes([block[2], block[3]]);
+    let [low, high] = [first, second].map(|colour| expand_565(colour).map(u32::from));
+
+    let blend = |first_part: u32, second_part: u32, total: u32| {
+        let mut colour = [0, 0, 0, 255];
+
+        for channel in 0..3 {
+            colour[channel] =
+                ((low[channel] * first_part + high[channel] * second_part) / total) as u8;
+        }
+
+        colour
+    };
+
+    let palette = if first > second || always_four {
+        [
+            blend(1, 0, 1),
+            blend(0, 1, 1),
+            blend(2, 1, 3),
+            blend(1, 2, 3),
+        ]
+    } else {
+        [blend(1, 0, 1), blend(0, 1, 1), blend(1, 1, 2), [0, 0, 0, 0]]
+    };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+
+    std::array::from_fn(|pixel| palette[(indices >> (2 * pixel)) as usize & 3])
+}
+
+/// Decodes one channel of a BC4 block
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let (first, second) = (block[0] as u32, block[1] as u32);
+
+    let palette: [u32; 8] = if first > second {
+        std::array::from_fn(|index| match index {
+            0 => first,
+            1 => second,
+            _ => ((8 - index as u32) * first + (index as u32 - 1) * second) / 7,
+        })
+    } else {
+        std::array::from_fn(|index| match index {
+            0 => first,
+            1 => second,
+            6 => 0,
+            7 => 255,
+            _ => ((6 - index as u32) * first + (index as u32 - 1) * second) / 5,
+        })
+    };
+
+    let mut index_bytes = [0; 8];
+    index_bytes[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(index_bytes);
+
+    std::array::from_fn(|pixel| palette[(indices >> (3 * pixel)) as usize & 7] as u8)
+}
+
+/// Decodes a BC7 block, which matknife always writes in mode 6: one pair of
+/// 7-bit RGBA endpoints, each with its own low bit, and 4-bit indices
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    const WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+    let mut bits = Bits::new(block);
+    assert_eq!(bits.take(7), 1 << 6, "BC7 block is not in mode 6");
+
+    let mut endpoints = [[0_u32; 4]; 2];
+
+    for channel in 0..4 {
+        for endpoint in &mut endpoints {
+            endpoint[channel] = bits.take(7) as u32;
+        }
+    }
+
+    for endpoint in &mut endpoints {
+        let low_bit = bits.take(1) as u32;
+
+        for value in endpoint.iter_mut() {
+            *value = (*value << 1) | low_bit;
+        }
+    }
+
+    std::array::from_fn(|pixel| {
+        // The first index has its top bit left out, as it is always 0
+        let weight = WEIGHTS[bits.take(if pixel == 0 { 3 } else { 4 }) as usize];
+
+        std::array::from_fn(|channel| {
+            (((64 - weight) * endpoints[0][channel] + weight * endpoints[1][channel] + 32) >> 6)
+                as u8
+        })
+    })
+}
+
+/// Decodes the 4x4 blocks of a compressed format to pixels
+fn decode_block(format: Format, block: &[u8]) -> [[u8; 4]; 16] {
+    match format {
+        Format::Bc1 => decode_bc1(block, false),
+        Format::Bc3 => {
+            let alpha = decode_bc4(&block[..8]);
+            let mut pixels = decode_bc1(&block[8..], true);
+
+            for (pixel, alpha) in pixels.iter_mut().zip(alpha) {
+                pixel[3] = alpha;
+            }
+
+            pixels
+        }
+        Format::Bc4 => decode_bc4(block).map(|value| [value, 0, 0, 255]),
+        Format::Bc5 => {
+            let (red, green) = (decode_bc4(&block[..8]), decode_bc4(&block[8..]));
+
+            std::array::from_fn(|pixel| [red[pixel], green[pixel], 0, 255])
+        }
+        Format::Bc7 => decode_bc7(block),
+        _ => unreachable!("{} is not compressed", format),
+    }
+}
+
+/// Reads back the largest mip level of a DDS file, checking its DXGI
+/// format
+fn read_dds(data: &[u8], format: Format, dxgi_format: u32) -> RgbaImage {
+    assert_eq!(&data[..4], b"DDS ", "not a DDS file");
+    assert_eq!(&data[84..88], b"DX10", "no DX10 header");
+    assert_eq!(read_u32(data, 128), dxgi_format, "DXGI format");
+
+    let (height, width) = (read_u32(data, 12), read_u32(data, 16));
+    let data = &data[148..];
+
+    if format == Format::Rgba {
+        let size = (width * height * 4) as usize;
+
+        return RgbaImage::from_raw(width, height, data[..size].to_vec()).unwrap();
+    }
+
+    let block_size = if matches!(format, Format::Bc1 | Format::Bc4) {
+        8
+    } else {
+        16
+    };
+    let blocks_across = width.div_ceil(4);
+
+    RgbaImage::from_fn(width, height, |x_position, y_position| {
+        let block = ((y_position / 4) * blocks_across + x_position / 4) as usize;
+        let pixels = decode_block(format, &data[block * block_size..][..block_size]);
+
+        Rgba(pixels[(y_position % 4 * 4 + x_position % 4) as usize])
+    })
+}
+
+/// The largest difference between the channels of two images, out of 255
+fn largest_error(decoded: &RgbaImage, original: &RgbaImage, channels: usize) -> u8 {
+    decoded
+        .pixels()
+        .zip(original.pixels())
+        .flat_map(|(decoded, original)| {
+            (0..channels).map(move |channel| decoded[channel].abs_diff(original[channel]))
+        })
+        .max()
+        .unwrap()
+}
+
+#[test]
+fn dds_files_decode_to_the_pixels_they_were_made_from() {
+    let colour = common::fixture(Pattern::Gradient, ColorType::Rgba8, 16, 8);
+    let with_alpha = common::packed_fixture(
+        [
+            Pattern::Gradient,
+            Pattern::Gradient,
+            Pattern::Gradient,
+            Pattern::VerticalGradient,
+        ],
+        16,
+        8,
+    );
+    let grey = common::fixture(Pattern::Gradient, ColorType::L8, 16, 8);
+    let normal = common::packed_fixture(
+        [
+            Pattern::Gradient,
+            Pattern::VerticalGradient,
+            Pattern::Edges,
+            Pattern::Edges,
+        ],
+        16,
+        8,
+    );
+    let noise = common::fixture(Pattern::Noise(5), ColorType::Rgba8, 10, 6);
+
+    // Each format with an image it suits, the DXGI format it is written
+    // as in linear space, the channels it stores, and the largest error
+    // allowed out of 255
+    let cases: [(Format, &DynamicImage, u32, usize, u8); 6] = [
+        (Format::Bc1, &colour, 71, 3, 12),
+        (Format::Bc3, &with_alpha, 77, 4, 12),
+        (Format::Bc4, &grey, 80, 1, 4),
+        (Format::Bc5, &normal, 83, 2, 6),
+        (Format::Bc7, &colour, 98, 4, 6),
+        (Format::Rgba, &noise, 28, 4, 0),
+    ];
+
+    for (format, image, dxgi_format, channels, tolerance) in cases {
+        let data = dds::encode(
+            std::slice::from_ref(image),
+            ColorSpace::Linear,
+            None,
+            format,
+        )
+        .unwrap();
+        let decoded = read_dds(&data, format, dxgi_format);
+
+        let error = largest_error(&decoded, &image.to_rgba8(), channels);
+        assert!(
+            error <= tolerance,
+            "{} is off by up to {}, more than {}",
+            format,
+            error,
+            tolerance
+        );
+    }
+}
+
+#[test]
+fn dds_files_keep_black_and_white() {
+    let edges = common::fixture(Pattern::Edges, ColorType::Rgba8, 8, 8);
+
+    // BC7 mode 6 shares the low bit of each endpoint between its channels,
+    // so opaque black is stored with a 1 in its colour channels
+    for (format, dxgi_format, channels, tolerance) in [
+        (Format::Bc1, 72, 3, 0),
+        (Format::Bc3, 78, 4, 0),
+        (Format::Bc4, 80, 1, 0),
+        (Format::Bc5, 83, 2, 0),
+        (Format::Bc7, 99, 4, 1),
+    ] {
+        let data =
+            dds::encode(std::slice::from_ref(&edges), ColorSpace::Srgb, None, format).unwrap();
+        let decoded = read_dds(&data, format, dxgi_format);
+
+        assert_eq!(
+            largest_error(&decoded, &edges.to_rgba8(), channels),
+            tolerance,
+            "{} changed black or white",
+            format
+        );
+    }
+}
+
+/// A greyscale roughness map which is the same everywhere
+fn even_roughness() -> DynamicImage {
+    DynamicImage::ImageLuma16(image::ImageBuffer::from_pixel(
+        16,
+        16,
+        image::Luma([u16::MAX / 4]),
+    ))
+}
+
+/// The mip levels of roughness in red, without and with widening by the
+/// bumps of `normal`
+fn mip_levels(
+    roughness: &DynamicImage,
+    normal: &DynamicImage,
+) -> (Vec<DynamicImage>, Vec<DynamicImage>) {
+    let plain = mipmap::generate(roughness, Some(ColorSpace::Linear), None, None);
+    let widened = mipmap::generate(
+        roughness,
+        Some(ColorSpace::Linear),
+        Some((0, false)),
+        Some(&Toksvig {
+            normal,
+            curve: RoughnessCurve::Linear,
+        }),
+    );
+
+    (plain, widened)
+}
+
+fn mean(image: &DynamicImage) -> f32 {
+    let values = common::grey_f32(image);
+
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+#[test]
+fn smaller_mip_levels_widen_roughness_by_the_bumps_they_lose() {
+    let flat = DynamicImage::ImageRgb8(image::ImageBuffer::from_pixel(
+        16,
+        16,
+        image::Rgb([128, 128, 255]),
+    ));
+
+    let (plain, widened) = mip_levels(&even_roughness(), &flat);
+
+    for (level, (plain, widened)) in plain.iter().zip(&widened).enumerate() {
+        let difference =
+            common::max_difference(&common::grey_f32(plain), &common::grey_f32(widened));
+        assert!(
+            difference < 1e-3,
+            "level {} of a flat material was widened by {}",
+            level,
+            difference
+        );
+    }
+
+    // Normals tilted a little every which way, so that averaging them
+    // never makes them so short that roughness reaches 1
+    let mut noise = Noise::new(21);
+    let bumpy = DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(16, 16, |_, _| {
+        let mut tilt = || (103 + noise.below(51)) as u8;
+
+        image::Rgb([tilt(), tilt(), 255])
+    }));
+
+    let (plain, widened) = mip_levels(&even_roughness(), &bumpy);
+
+    assert_eq!(widened.len(), 5, "16x16 down to 1x1");
+    assert_eq!(widened[0], plain[0], "the first level must not change");
+
+    // Each smaller level averages more bumps together, so loses more of
+    // them and is widened more than the last
+    let mut last = mean(&widened[0]);
+
+    for (level, (plain, widened)) in plain.iter().zip(&widened).enumerate().skip(1) {
+        for (plain, widened) in common::grey(plain).iter().zip(common::grey(widened)) {
+            assert!(
+                widened >= *plain,
+                "level {} was made smoother ({} from {})",
+                level,
+                widened,
+                plain
+            );
+        }
+
+        let roughness = mean(widened);
+        assert!(
+            roughness > last,
+            "level {} has roughness {}, no more than the level before's {}",
+            level,
+            roughness,
+            last
+        );
+
+        last = roughness;
+    }
+
+    // Greyscale maps are widened as much as the red channel of a packed
+    // texture is, rather than blended with the roughness they started with
+    let packed = DynamicImage::ImageRgba16(even_roughness().to_rgba16());
+    let (_, packed_widened) = mip_levels(&packed, &bumpy);
+
+    for (level, (grey, packed)) in widened.iter().zip(&packed_widened).enumerate() {
+        let difference = common::max_difference(&common::grey_f32(grey), &common::grey_f32(packed));
+        assert!(
+            difference < 1e-3,
+            "greyscale level {} is off from red by {}",
+            level,
+            difference
+        );
+    }
+}
@@ -0,0 +1,568 @@
+//! Splitting packed textures into separate maps and merging them back,
+//! for every layout and kind of input image, along with other conversions
+//! which should give back what they started with
+
+mod common;
+
+use common::{Noise, Pattern, TempDir, COLOR_TYPES};
+use image::{ColorType, DynamicImage};
+use matknife::depth::BitDepth;
+use matknife::layout::{self, Layout, Map, MergeOptions, SplitOptions, LAYOUTS};
+use matknife::normal::{self, NormalConvention, NormalOptions};
+use matknife::resize::{self, Filter};
+use matknife::{image_file, workflow, RoughnessCurve};
+
+const WIDTH: u32 = 7;
+const HEIGHT: u32 = 5;
+
+/// How many random cases each property is checked with
+const CASES: u32 = 64;
+
+/// Merges maps given in any order, in the order the layout needs them
+fn merge(layout: &Layout, mut maps: Vec<(Map, DynamicImage)>) -> DynamicImage {
+    let images = layout
+        .maps
+        .iter()
+        .map(|map| {
+            let position = maps.iter().position(|(split_map, _)| split_map == map);
+
+            position.map(|position| maps.remove(position).1)
+        })
+        .collect();
+
+    layout.merge(images, RoughnessCurve::Linear).unwrap()
+}
+
+/// Float images are not stored exactly, so they only need to be this close
+fn tolerance(color_type: ColorType) -> f32 {
+    if BitDepth::of(&common::fixture(Pattern::Gradient, color_type, 1, 1)) == BitDepth::Float {
+        1e-6
+    } else {
+        0.0
+    }
+}
+
+#[test]
+fn merge_of_split_gives_back_the_packed_texture() {
+    let mut seed = 0x2545f491;
+
+    for layout in LAYOUTS {
+        for &color_type in COLOR_TYPES {
+            let maps: Vec<(Map, DynamicImage)> = layout
+                .maps
+                .iter()
+                .map(|map| {
+                    seed += 1;
+                    let image = common::fixture(Pattern::Noise(seed), color_type, WIDTH, HEIGHT);
+
+                    (*map, image)
+                })
+                .collect();
+
+            let packed = merge(layout, maps.clone());
+            let split = layout
+                .split(packed.clone(), RoughnessCurve::Linear)
+                .unwrap();
+
+            for (map, image) in &maps {
+                let (_, split_image) = split
+                    .iter()
+                    .find(|(split_map, _)| split_map == map)
+                    .unwrap_or_else(|| panic!("{} has no {} map", layout.name, map));
+
+                let difference = common::max_difference(
+                    &common::grey_f32(split_image),
+                    &common::grey_f32(image),
+                );
+
+                assert!(
+                    difference <= tolerance(color_type),
+                    "{} map of {} from {:?} is off by {}",
+                    map,
+                    layout.name,
+                    color_type,
+                    difference
+                );
+            }
+
+            let merged = merge(layout, split);
+
+            assert_eq!(
+                merged.color(),
+                packed.color(),
+                "{} from {:?}",
+                layout.name,
+                color_type
+            );
+
+            let difference =
+                common::max_difference(merged.to_rgba32f().as_raw(), packed.to_rgba32f().as_raw());
+
+            assert!(
+                difference <= tolerance(color_type),
+                "{} from {:?} is off by {}",
+                layout.name,
+                color_type,
+                difference
+            );
+        }
+    }
+}
+
+#[test]
+fn unity_merge_keeps_metallic_in_colour_channels() {
+    let mut seed = 0x9e3779b9;
+
+    for &color_type in COLOR_TYPES {
+        if BitDepth::of(&common::fixture(Pattern::Gradient, color_type, 1, 1)) == BitDepth::Float {
+            continue;
+        }
+
+        seed += 2;
+        let metallic = common::fixture(Pattern::Noise(seed), color_type, WIDTH, HEIGHT);
+        let roughness = common::fixture(Pattern::Noise(seed + 1), color_type, WIDTH, HEIGHT);
+
+        let packed = layout::UNITY
+            .merge(
+                vec![Some(metallic.clone()), Some(roughness.clone())],
+                RoughnessCurve::Linear,
+            )
+            .unwrap();
+
+        let expected = common::grey(&metallic);
+
+        for (pixel, (metallic, roughness)) in packed
+            .to_rgba16()
+            .pixels()
+            .zip(expected.iter().zip(common::grey(&roughness)))
+        {
+            assert_eq!(
+                [pixel[0], pixel[1], pixel[2], pixel[3]],
+                [*metallic, *metallic, *metallic, u16::MAX - roughness],
+                "from {:?}",
+                color_type
+            );
+        }
+    }
+}
+
+#[test]
+fn split_of_merge_gives_back_maps_of_any_size_and_depth() {
+    let mut noise = Noise::new(0x1234567);
+
+    for case in 0..CASES {
+        let layout = *noise.pick(LAYOUTS);
+        let color_type = *noise.pick(COLOR_TYPES);
+        let width = noise.below(17) as u32 + 1;
+        let height = noise.below(17) as u32 + 1;
+
+        let maps: Vec<(Map, DynamicImage)> = layout
+            .maps
+            .iter()
+            .map(|map| {
+                let pattern = *noise.pick(&[
+                    Pattern::Gradient,
+                    Pattern::VerticalGradient,
+                    Pattern::Edges,
+                    Pattern::Noise(case * 4 + 1),
+                ]);
+
+                (*map, common::fixture(pattern, color_type, width, height))
+            })
+            .collect();
+
+        // Any map can be missing, as long as one is there
+        let present: Vec<(Map, DynamicImage)> = maps
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index == 0 || noise.below(3) > 0)
+            .map(|(_, map)| map.clone())
+            .collect();
+
+        let packed = merge(layout, present.clone());
+
+        assert_eq!(
+            (packed.width(), packed.height()),
+            (width, height),
+            "case {}: {} from {:?}",
+            case,
+            layout.name,
+            color_type
+        );
+        assert_eq!(
+            BitDepth::of(&packed),
+            BitDepth::of(&present[0].1),
+            "case {}: {} from {:?}",
+            case,
+            layout.name,
+            color_type
+        );
+
+        for (map, image) in layout.split(packed, RoughnessCurve::Linear).unwrap() {
+            let expected = match present.iter().find(|(present_map, _)| *present_map == map) {
+                Some((_, original)) => common::grey_f32(original),
+                None => vec![map.default_value() as f32 / 255.0; (width * height) as usize],
+            };
+
+            let difference = common::max_difference(&common::grey_f32(&image), &expected);
+
+            assert!(
+                difference <= tolerance(color_type),
+                "case {}: {} map of {} from {:?} is off by {}",
+                case,
+                map,
+                layout.name,
+                color_type,
+                difference
+            );
+        }
+    }
+}
+
+#[test]
+fn split_reads_smoothness_from_wherever_it_is_told() {
+    let metallic = common::fixture(Pattern::Gradient, ColorType::Rgb16, WIDTH, HEIGHT);
+    let smoothness = common::fixture(Pattern::Noise(7), ColorType::L16, WIDTH, HEIGHT);
+
+    let options = SplitOptions {
+        smoothness_image: Some(smoothness.clone()),
+        ..SplitOptions::default()
+    };
+
+    let maps = layout::UNITY
+        .split_with(metallic, RoughnessCurve::Linear, &options)
+        .unwrap();
+    let roughness = &maps
+        .iter()
+        .find(|(map, _)| *map == Map::Roughness)
+        .unwrap()
+        .1;
+
+    let inverted: Vec<u16> = common::grey(&smoothness)
+        .iter()
+        .map(|value| u16::MAX - value)
+        .collect();
+
+    assert_eq!(common::grey(roughness), inverted);
+
+    let opaque = common::fixture(Pattern::Gradient, ColorType::Rgb8, WIDTH, HEIGHT);
+
+    assert!(layout::UNITY
+        .split(opaque.clone(), RoughnessCurve::Linear)
+        .is_err());
+
+    let options = SplitOptions {
+        default_smoothness: Some(0.25),
+        ..SplitOptions::default()
+    };
+
+    let maps = layout::UNITY
+        .split_with(opaque, RoughnessCurve::Linear, &options)
+        .unwrap();
+    let roughness = &maps
+        .iter()
+        .find(|(map, _)| *map == Map::Roughness)
+        .unwrap()
+        .1;
+
+    assert!(common::grey(roughness)
+        .iter()
+        .all(|value| *value == 191 * 257));
+}
+
+#[test]
+fn merge_resizes_maps_to_the_same_size_when_told() {
+    let metallic = common::fixture(Pattern::Noise(9), ColorType::L8, 8, 6);
+    let roughness = common::fixture(Pattern::Noise(10), ColorType::L8, 4, 3);
+
+    let images = || vec![Some(metallic.clone()), Some(roughness.clone())];
+
+    assert!(layout::UNITY
+        .merge(images(), RoughnessCurve::Linear)
+        .is_err());
+
+    let options = MergeOptions {
+        resize_to: Some(resize::Target::Larger),
+        resize_filter: Filter::Nearest,
+        ..MergeOptions::default()
+    };
+
+    let merged = layout::UNITY
+        .merge_with(images(), RoughnessCurve::Linear, &options)
+        .unwrap();
+
+    assert_eq!((merged.width(), merged.height()), (8, 6));
+
+    let maps = layout::UNITY.split(merged, RoughnessCurve::Linear).unwrap();
+    let split_roughness = &maps
+        .iter()
+        .find(|(map, _)| *map == Map::Roughness)
+        .unwrap()
+        .1;
+    let enlarged = resize::resize(&roughness, 8, 6, Filter::Nearest);
+
+    assert_eq!(common::grey(&maps[0].1), common::grey(&metallic));
+    assert_eq!(common::grey(split_roughness), common::grey(&enlarged));
+}
+
+#[test]
+fn roughness_curves_give_back_roughness() {
+    for curve in [
+        RoughnessCurve::Linear,
+        RoughnessCurve::Squared,
+        RoughnessCurve::Phong,
+    ] {
+        // Phong exponents top out at 8191, which is a roughness of about
+        // 0.13, so smoother values cannot come back
+        let smoothest = if curve == RoughnessCurve::Phong {
+            0.15
+        } else {
+            0.0
+        };
+
+        for step in 0..=1000 {
+            let roughness = step as f32 / 1000.0;
+
+            if roughness < smoothest {
+                continue;
+            }
+
+            let smoothness = curve.smoothness(roughness);
+
+            assert!(
+                (0.0..=1.0).contains(&smoothness),
+                "{:?} smoothness of {} is {}",
+                curve,
+                roughness,
+                smoothness
+            );
+
+            let back = curve.roughness(smoothness);
+
+            assert!(
+                (back - roughness).abs() < 1e-3,
+                "{:?} roughness of {} comes back as {}",
+                curve,
+                roughness,
+                back
+            );
+
+            let alpha = curve.roughness_from_alpha(curve.alpha(roughness));
+
+            assert!(
+                (alpha - roughness).abs() < 1e-3,
+                "{:?} roughness of {} comes back from alpha as {}",
+                curve,
+                roughness,
+                alpha
+            );
+        }
+    }
+}
+
+#[test]
+fn flipping_normal_maps_twice_gives_them_back() {
+    // Normal maps are always colour images
+    for &color_type in COLOR_TYPES.iter().filter(|color| color.has_color()) {
+        let original = common::fixture(Pattern::Noise(7), color_type, WIDTH, HEIGHT);
+
+        let flipped = normal::convert(
+            &original,
+            NormalConvention::OpenGl,
+            NormalConvention::DirectX,
+            NormalOptions::default(),
+        );
+        let back = normal::convert(
+            &flipped,
+            NormalConvention::DirectX,
+            NormalConvention::OpenGl,
+            NormalOptions::default(),
+        );
+
+        assert_eq!(back.color(), original.color(), "from {:?}", color_type);
+
+        let difference =
+            common::max_difference(back.to_rgba32f().as_raw(), original.to_rgba32f().as_raw());
+
+        assert!(
+            difference <= tolerance(color_type).max(1e-6),
+            "from {:?} is off by {}",
+            color_type,
+            difference
+        );
+    }
+}
+
+#[test]
+fn box_shrinking_gives_back_nearest_enlarging() {
+    for &color_type in COLOR_TYPES {
+        let original = common::fixture(Pattern::Noise(13), color_type, WIDTH, HEIGHT);
+
+        for factor in [2, 3] {
+            let enlarged =
+                resize::resize(&original, WIDTH * factor, HEIGHT * factor, Filter::Nearest);
+            let back = resize::resize(&enlarged, WIDTH, HEIGHT, Filter::Box);
+
+            assert_eq!(back.color(), original.color(), "from {:?}", color_type);
+
+            let difference =
+                common::max_difference(back.to_rgba32f().as_raw(), original.to_rgba32f().as_raw());
+
+            assert!(
+                difference <= tolerance(color_type).max(1e-6),
+                "from {:?} enlarged {} times is off by {}",
+                color_type,
+                factor,
+                difference
+            );
+        }
+    }
+}
+
+#[test]
+fn image_files_hold_every_depth() {
+    let directory = TempDir::new("image-files");
+
+    for (color_type, extension) in [
+        (ColorType::L8, "png"),
+        (ColorType::Rgba8, "png"),
+        (ColorType::L16, "png"),
+        (ColorType::Rgba16, "png"),
+        (ColorType::Rgb16, "tiff"),
+        (ColorType::Rgb8, "tga"),
+        (ColorType::Rgb32F, "exr"),
+        (ColorType::Rgba32F, "exr"),
+    ] {
+        let original = common::fixture(Pattern::Noise(11), color_type, WIDTH, HEIGHT);
+        let path = directory.join(format!("{:?}.{}", color_type, extension));
+
+        image_file::save(&original, &path, None).unwrap();
+        let opened = image_file::open(&path).unwrap();
+
+        assert_eq!(
+            BitDepth::of(&opened),
+            BitDepth::of(&original),
+            "{:?} as {}",
+            color_type,
+            extension
+        );
+        assert_eq!(
+            opened.to_rgba32f().as_raw(),
+            original.to_rgba32f().as_raw(),
+            "{:?} as {}",
+            color_type,
+            extension
+        );
+    }
+}
+
+#[test]
+fn exr_files_without_alpha_keep_their_colours() {
+    let directory = TempDir::new("exr-rgb");
+    let path = directory.join("Colours.exr");
+
+    // A different pattern in each channel, so that channels read from the
+    // wrong place or with the wrong stride are caught
+    let original = DynamicImage::ImageRgb32F(
+        common::packed_fixture(
+            [
+                Pattern::Gradient,
+                Pattern::VerticalGradient,
+                Pattern::Noise(17),
+                Pattern::Edges,
+            ],
+            WIDTH,
+            HEIGHT,
+        )
+        .to_rgb32f(),
+    );
+
+    image_file::save(&original, &path, None).unwrap();
+    let opened = image_file::open(&path).unwrap();
+
+    assert_eq!(opened.color(), ColorType::Rgb32F);
+    assert_eq!(opened.to_rgb32f().as_raw(), original.to_rgb32f().as_raw());
+}
+
+#[test]
+fn workflow_conversions_keep_the_depth_of_their_inputs() {
+    let base_color = common::fixture(Pattern::Gradient, ColorType::Rgb16, WIDTH, HEIGHT);
+    let roughness = common::fixture(Pattern::Noise(11), ColorType::L16, WIDTH, HEIGHT);
+
+    let specular = workflow::metallic_to_specular(&workflow::MetallicRoughness {
+        base_color,
+        metallic: DynamicImage::new_luma16(WIDTH, HEIGHT),
+        roughness: roughness.clone(),
+    })
+    .unwrap();
+
+    assert_eq!(specular.diffuse.color(), ColorType::Rgb16);
+    assert_eq!(specular.specular_glossiness.color(), ColorType::Rgba16);
+
+    let metallic = workflow::specular_to_metallic(&specular, None).unwrap();
+
+    assert_eq!(metallic.base_color.color(), ColorType::Rgb16);
+    assert_eq!(metallic.metallic.color(), ColorType::L16);
+    assert_eq!(metallic.roughness.color(), ColorType::L16);
+
+    // Roughness only goes through glossiness and back, so keeps every bit
+    assert_eq!(common::grey(&metallic.roughness), common::grey(&roughness));
+}
+
+#[test]
+fn metallic_to_specular_and_back_gives_back_dielectrics_and_metals() {
+    let (width, height) = (16, 8);
+
+    // Colours bright enough that dark dielectrics are not mistaken for
+    // metals, which reflect at least as much as dielectrics do
+    let base_color = DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(
+        width,
+        height,
+        |x_position, y_position| {
+            let bright = |position: u32, size: u32| {
+                (u16::MAX as u32 * (2 * size + position) / (3 * size)) as u16
+            };
+
+            image::Rgb([
+                bright(x_position, width),
+                bright(y_position, height),
+                bright(width - 1 - x_position, width),
+            ])
+        },
+    ));
+    let metallic = DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(
+        width,
+        height,
+        |x_position, _| image::Luma([if x_position < width / 2 { 0 } else { u16::MAX }]),
+    ));
+    let roughness = common::fixture(Pattern::Noise(17), ColorType::L16, width, height);
+
+    let specular = workflow::metallic_to_specular(&workflow::MetallicRoughness {
+        base_color: base_color.clone(),
+        metallic: metallic.clone(),
+        roughness: roughness.clone(),
+    })
+    .unwrap();
+    let converted = workflow::specular_to_metallic(&specular, None).unwrap();
+
+    let colours = |image: &DynamicImage| -> Vec<f32> { image.to_rgb32f().into_raw() };
+
+    let metallic_difference = common::max_difference(
+        &common::grey_f32(&converted.metallic),
+        &common::grey_f32(&metallic),
+    );
+    let colour_difference =
+        common::max_difference(&colours(&converted.base_color), &colours(&base_color));
+
+    assert!(
+        metallic_difference < 1e-3,
+        "metallic is off by {}",
+        metallic_difference
+    );
+    assert!(
+        colour_difference < 1e-3,
+        "base colour is off by {}",
+        colour_difference
+    );
+    assert_eq!(common::grey(&converted.roughness), common::grey(&roughness));
+}